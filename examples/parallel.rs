@@ -1,4 +1,4 @@
-use std::{error::Error, sync::Arc, time::Instant};
+use std::{sync::Arc, time::Instant};
 
 use alhc::prelude::*;
 use alhc::*;
@@ -13,50 +13,44 @@ fn main() {
     async {
         let client = Arc::new(get_client_builder().build().unwrap());
 
-        let mut success = 0;
-        let mut failed = 0;
-
         println!("Sending httpbin");
 
-        for (i, r) in join_all((0..10).map(|i| {
+        let url = "http://httpbin.org/anything";
+        let results: JoinResults<String> = join_all((0..10).map(|i| {
             let client = client.clone();
             async move {
                 let instant = Instant::now();
-                let r = client
-                    .post("http://httpbin.org/anything")?
-                    .body_string(format!("Requesting {}", i).repeat(4));
-                println!("Requesting {}", i);
-                let r = r.await?.recv_string().await;
+                let r = async {
+                    let r = client
+                        .post(url)?
+                        .body_string(format!("Requesting {}", i).repeat(4))
+                        .tag(format!("request-{}", i));
+                    println!("Requesting {}", i);
+                    DynResult::Ok(r.await?.recv_string().await?)
+                }
+                .await;
                 if let Err(err) = &r {
                     println!("Request {} Error: {}", i, err);
                 } else {
                     let e = instant.elapsed().as_millis();
                     println!("Request {} ok in {}ms", i, e);
                 }
-                Ok::<String, Box<dyn Error>>(r?)
+                (format!("{}#{}", url, i), r)
             }
         }))
         .await
         .into_iter()
-        .enumerate()
-        {
-            match r {
-                Ok(_) => {
-                    success += 1;
-                    // println!("Request {} ok", i);
-                }
-                Err(err) => {
-                    failed += 1;
-                    println!("Request {} Error: {}", i, err);
-                }
-            }
+        .collect();
+
+        for (url, err) in results.failures() {
+            println!("Request to {} Error: {}", url, err);
         }
 
         println!(
             "Sent {} requests, {} succeed, {} failed",
-            success + failed,
-            success,
-            failed
+            results.total_count(),
+            results.success_count(),
+            results.failure_count()
         );
 
         DynResult::Ok(())
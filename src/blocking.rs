@@ -0,0 +1,266 @@
+//! A synchronous facade over the async [`crate::Client`], for simple scripts
+//! that don't want to pull in an async runtime.
+//!
+//! Every call blocks the current thread until it's done, by driving the async
+//! implementation with [`futures_lite::future::block_on`]. Just like the async
+//! one, [`Client`] can be shared between threads, and requests sent from
+//! different threads run concurrently. Don't call these methods inside an
+//! async task, as they will block the executor thread.
+//!
+//! ```no_run
+//! use alhc::blocking::Client;
+//!
+//! # fn run() -> alhc::DynResult {
+//! let client = Client::new()?;
+//! let text = client.get("https://httpbin.org/get")?.send()?.recv_string()?;
+//! println!("{text}");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+
+use futures_lite::{future::block_on, AsyncReadExt};
+
+use crate::{prelude::*, DynResult, Method, ResponseBody};
+
+/// A blocking client, which wraps an async [`crate::Client`].
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: crate::Client,
+}
+
+impl Client {
+    /// Build a blocking client with the default options.
+    pub fn new() -> DynResult<Self> {
+        Ok(Self::from_async(crate::get_client_builder().build()?))
+    }
+
+    /// Wrap an async client, so its options like timeout will be kept.
+    pub fn from_async(inner: crate::Client) -> Self {
+        Self { inner }
+    }
+
+    /// The wrapped async client.
+    pub fn as_async(&self) -> &crate::Client {
+        &self.inner
+    }
+
+    /// Cancel all the requests in flight and close the cached connections,
+    /// see [`crate::Client::shutdown`].
+    pub fn shutdown(&self) {
+        self.inner.shutdown()
+    }
+
+    /// Create a request with a method and a url.
+    pub fn request(&self, method: Method, url: &str) -> DynResult<Request> {
+        Ok(Request {
+            inner: self.inner.request(method, url)?,
+        })
+    }
+
+    /// A wrapper of `Client::request(Method::GET, url)`
+    pub fn get(&self, url: &str) -> DynResult<Request> {
+        self.request(Method::GET, url)
+    }
+
+    /// A wrapper of `Client::request(Method::POST, url)`
+    pub fn post(&self, url: &str) -> DynResult<Request> {
+        self.request(Method::POST, url)
+    }
+
+    /// Download the body of `url` into a file created at `path`, split into
+    /// ranged requests, see [`crate::Client::download_to_file`].
+    #[cfg(feature = "fs")]
+    pub fn download_to_file(
+        &self,
+        url: &str,
+        path: impl AsRef<std::path::Path>,
+        options: crate::DownloadOptions,
+    ) -> DynResult<u64> {
+        block_on(self.inner.download_to_file(url, path, options))
+    }
+
+    /// Resume downloading the body of `url` into the file at `path`, see
+    /// [`crate::Client::resume_download`].
+    #[cfg(feature = "fs")]
+    pub fn resume_download(&self, url: &str, path: impl AsRef<std::path::Path>) -> DynResult<u64> {
+        block_on(self.inner.resume_download(url, path))
+    }
+}
+
+/// A blocking request, call [`Request::send`] to send it.
+pub struct Request {
+    inner: crate::prelude::Request,
+}
+
+impl Request {
+    /// Add a header value, will keep exists same header.
+    pub fn header(self, header: &str, value: &str) -> Self {
+        Self {
+            inner: self.inner.header(header, value),
+        }
+    }
+
+    /// Add several header values at once, each of them kept like
+    /// [`Request::header`].
+    pub fn headers<'a>(self, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        Self {
+            inner: self.inner.headers(headers),
+        }
+    }
+
+    /// Get the value of a header set on the request, the values of a header
+    /// added multiple times are joined with `, `.
+    pub fn get_header(&self, header: &str) -> Option<String> {
+        self.inner.get_header(header)
+    }
+
+    /// Replace a header value, add if not exists.
+    pub fn replace_header(self, header: &str, value: &str) -> Self {
+        Self {
+            inner: self.inner.replace_header(header, value),
+        }
+    }
+
+    /// Only get the body if it doesn't match the ETag of a cached copy.
+    pub fn if_none_match(self, etag: &str) -> Self {
+        Self {
+            inner: self.inner.if_none_match(etag),
+        }
+    }
+
+    /// Only get the body if it has changed since the date of a cached copy.
+    pub fn if_modified_since(self, date: &str) -> Self {
+        Self {
+            inner: self.inner.if_modified_since(date),
+        }
+    }
+
+    /// Only get a part of the body, from the byte at `start` to the one at
+    /// `end` included, or to the end of the body if `end` is `None`.
+    pub fn range(self, start: u64, end: Option<u64>) -> Self {
+        Self {
+            inner: self.inner.range(start, end),
+        }
+    }
+
+    /// Only get the last `len` bytes of the body.
+    pub fn range_suffix(self, len: u64) -> Self {
+        Self {
+            inner: self.inner.range_suffix(len),
+        }
+    }
+
+    /// Only send the body once the server accepts the request.
+    pub fn expect_continue(self) -> Self {
+        Self {
+            inner: self.inner.expect_continue(),
+        }
+    }
+
+    /// Remove all the values of a header, so the header isn't sent.
+    pub fn remove_header(self, header: &str) -> Self {
+        Self {
+            inner: self.inner.remove_header(header),
+        }
+    }
+
+    /// Answer the Digest challenge of a `401 Unauthorized` response with the
+    /// user name and the password.
+    pub fn digest_auth(self, user: &str, pass: &str) -> Self {
+        Self {
+            inner: self.inner.digest_auth(user, pass),
+        }
+    }
+
+    /// Fail the request if it isn't done by `at`, including reading the
+    /// whole body.
+    pub fn deadline(self, at: std::time::Instant) -> Self {
+        Self {
+            inner: self.inner.deadline(at),
+        }
+    }
+
+    /// Fail the request if it isn't done `timeout` from now, including
+    /// reading the whole body.
+    pub fn overall_timeout(self, timeout: std::time::Duration) -> Self {
+        Self {
+            inner: self.inner.overall_timeout(timeout),
+        }
+    }
+
+    /// Provide string data as a body in request
+    pub fn body_string(self, body: String) -> Self {
+        Self {
+            inner: self.inner.body_string(body),
+        }
+    }
+
+    /// Provide binary data as a body in request
+    pub fn body_bytes(self, body: Vec<u8>) -> Self {
+        Self {
+            inner: self.inner.body_bytes(body),
+        }
+    }
+
+    /// Compress the body with gzip and send it with `Content-Encoding: gzip`.
+    #[cfg(feature = "gzip")]
+    pub fn gzip_body(self) -> Self {
+        Self {
+            inner: self.inner.gzip_body(),
+        }
+    }
+
+    /// Send the request and wait for the response headers.
+    pub fn send(self) -> DynResult<Response> {
+        Ok(Response {
+            inner: block_on(self.inner)?,
+        })
+    }
+}
+
+/// A blocking response, which also implements [`std::io::Read`] to read the
+/// body in chunks.
+pub struct Response {
+    inner: crate::prelude::Response,
+}
+
+impl Response {
+    /// The status code of the response.
+    pub fn status_code(&self) -> u16 {
+        self.inner.status_code()
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HashMap<String, String> {
+        self.inner.headers()
+    }
+
+    /// Receive all data in memory and return a [`ResponseBody`]
+    pub fn recv(self) -> std::io::Result<ResponseBody> {
+        block_on(self.inner.recv())
+    }
+
+    /// Convenient method to receive data as string.
+    pub fn recv_string(self) -> std::io::Result<String> {
+        block_on(self.inner.recv_string())
+    }
+
+    /// Convenient method to receive data as binary data.
+    pub fn recv_bytes(self) -> std::io::Result<Vec<u8>> {
+        block_on(self.inner.recv_bytes())
+    }
+
+    /// Receive data as binary data, failing once the body is larger than
+    /// `max` bytes.
+    pub fn recv_bytes_with_limit(self, max: usize) -> std::io::Result<Vec<u8>> {
+        block_on(self.inner.recv_bytes_with_limit(max))
+    }
+}
+
+impl std::io::Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        block_on(self.inner.read(buf))
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::Waker,
+};
+
+type AbortCallback = Box<dyn FnOnce() + Send + Sync>;
+
+#[derive(Default)]
+pub(crate) struct CancelState {
+    cancelled: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+    abort: Mutex<Option<AbortCallback>>,
+}
+
+impl Debug for CancelState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelState")
+            .field("cancelled", &self.cancelled)
+            .finish()
+    }
+}
+
+impl CancelState {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Remember the waker of the task polling the request, so it can be woken
+    /// up when the request is cancelled.
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut stored = self.waker.lock().unwrap();
+        if !stored.as_ref().map(|x| x.will_wake(waker)).unwrap_or(false) {
+            *stored = Some(waker.clone());
+        }
+    }
+
+    /// Set a platform specific callback that aborts the underlying transfer.
+    #[cfg(target_os = "windows")]
+    pub(crate) fn on_abort(&self, abort: impl FnOnce() + Send + Sync + 'static) {
+        *self.abort.lock().unwrap() = Some(Box::new(abort));
+    }
+
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Some(abort) = self.abort.lock().unwrap().take() {
+            abort();
+        }
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The requests of a client and its clones, so all of them can be cancelled
+/// at once by [`crate::Client::shutdown`].
+#[derive(Debug, Default)]
+pub(crate) struct CancelGroup {
+    /// The states of the requests, which may have been dropped already.
+    requests: Mutex<Vec<Weak<CancelState>>>,
+    shut_down: AtomicBool,
+}
+
+impl CancelGroup {
+    /// The cancel state of a new request, which is cancelled already if the
+    /// group has been shut down.
+    pub(crate) fn register(&self) -> Arc<CancelState> {
+        let state = Arc::new(CancelState::default());
+        let mut requests = self.requests.lock().unwrap();
+        if self.shut_down.load(Ordering::Acquire) {
+            state.cancel();
+        } else {
+            requests.retain(|x| x.strong_count() > 0);
+            requests.push(Arc::downgrade(&state));
+        }
+        state
+    }
+
+    /// Cancel the requests in flight and the ones registered later.
+    pub(crate) fn cancel_all(&self) {
+        let requests = {
+            let mut requests = self.requests.lock().unwrap();
+            self.shut_down.store(true, Ordering::Release);
+            std::mem::take(&mut *requests)
+        };
+        for state in requests.iter().filter_map(Weak::upgrade) {
+            state.cancel();
+        }
+    }
+}
+
+pub(crate) fn cancelled_error() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Interrupted,
+        "request has been cancelled",
+    )
+}
+
+/// A handle that can abort an in-flight request from anywhere.
+///
+/// Get one from [`crate::prelude::CommonRequest::cancellable`]. After
+/// [`CancelHandle::cancel`] is called, the request future or the response
+/// reading will resolve to an [`std::io::ErrorKind::Interrupted`] error.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    pub(crate) state: Arc<CancelState>,
+}
+
+impl CancelHandle {
+    /// Abort the request, does nothing if it has already been cancelled.
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    /// Whether [`CancelHandle::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.is_cancelled()
+    }
+}
@@ -1,11 +1,369 @@
-#[derive(Debug)]
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    cancel::CancelGroup, concurrency::ConcurrencyLimiter, rate_limit::RateLimiter, RetryPolicy,
+};
+
+/// An HTTP client, built by [`ClientBuilder`].
+///
+/// Cloning a client is cheap, the clones share the same WinHTTP session and
+/// connections on Windows, and the same rate limit. So changing the timeout
+/// with [`crate::prelude::CommonClient::set_timeout`] on Windows affects all
+/// of the clones.
+#[derive(Debug, Clone)]
 pub struct Client {
     #[cfg(target_os = "windows")]
-    pub(crate) h_session: crate::windows::Handle,
+    pub(crate) h_session: Arc<crate::windows::Handle>,
     #[cfg(target_os = "windows")]
     pub(crate) connections:
-        std::sync::Mutex<std::collections::HashMap<String, std::sync::Arc<crate::windows::Handle>>>,
+        Arc<std::sync::Mutex<std::collections::HashMap<String, crate::windows::CachedConnection>>>,
+    #[cfg(target_os = "windows")]
+    pub(crate) buffer_size: usize,
+    #[cfg(unix)]
+    pub(crate) timeout: Option<Duration>,
+    #[cfg(unix)]
+    pub(crate) http1_only: bool,
+    #[cfg(unix)]
+    pub(crate) local_address: Option<std::net::IpAddr>,
+    #[cfg(unix)]
+    pub(crate) ip_version: IpVersion,
+    #[cfg(unix)]
+    pub(crate) env_proxy: Option<Arc<crate::proxy::EnvProxy>>,
+    #[cfg(unix)]
+    pub(crate) min_tls_version: Option<TlsVersion>,
+    #[cfg(unix)]
+    pub(crate) host_uses: Arc<crate::unix::HostUses>,
+    pub(crate) connect_retries: u32,
+    pub(crate) pool_max_idle_per_host: Option<usize>,
+    pub(crate) allow_truncated_body: bool,
+    pub(crate) max_response_size: Option<u64>,
+    pub(crate) pinned_public_key: Option<[u8; 32]>,
+    pub(crate) auto_decompress: bool,
+    pub(crate) proxy_credentials: Option<Arc<(String, String)>>,
+    pub(crate) retry: Option<Arc<RetryPolicy>>,
+    pub(crate) rate_limiter: Option<Arc<RateLimiter>>,
+    pub(crate) concurrency_limiter: Option<Arc<ConcurrencyLimiter>>,
+    #[cfg(feature = "gzip")]
+    pub(crate) compress_threshold: Option<usize>,
+    pub(crate) requests: Arc<CancelGroup>,
+}
+
+impl Client {
+    /// Close the cached connections, so the next requests connect again,
+    /// picking up DNS changes or restarted servers without building a new
+    /// client. It also affects the clones of the client.
+    ///
+    /// The requests in flight keep their connections alive until they're
+    /// done. Currently has no effect on Unix, where curl manages its
+    /// connections by itself.
+    pub fn clear_connections(&self) {
+        #[cfg(target_os = "windows")]
+        self.connections.lock().unwrap().clear();
+    }
+
+    /// Cancel all the requests in flight of the client and its clones and
+    /// close the cached connections, for a graceful shutdown.
+    ///
+    /// The requests and the responses being read will resolve to an
+    /// [`std::io::ErrorKind::Interrupted`] error, and so will the requests
+    /// made afterwards, the client can't be used anymore.
+    pub fn shutdown(&self) {
+        self.requests.cancel_all();
+        self.clear_connections();
+    }
+}
+
+/// TLS protocol versions, used by [`ClientBuilder::min_tls_version`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls1_0,
+    Tls1_1,
+    Tls1_2,
+    Tls1_3,
+}
+
+/// IP versions used to connect, used by [`ClientBuilder::ip_version`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpVersion {
+    /// Use whichever the system resolves to.
+    #[default]
+    Auto,
+    /// Only connect over IPv4.
+    V4Only,
+    /// Only connect over IPv6.
+    V6Only,
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct ClientBuilder {}
+pub struct ClientBuilder {
+    pub(crate) default_timeout: Option<Duration>,
+    pub(crate) min_tls_version: Option<TlsVersion>,
+    pub(crate) buffer_size: Option<usize>,
+    pub(crate) http1_only: bool,
+    /// `None` keeps the default interval.
+    pub(crate) http2_keepalive: Option<Option<Duration>>,
+    pub(crate) rate_limit: Option<u32>,
+    pub(crate) max_concurrent: Option<usize>,
+    pub(crate) local_address: Option<std::net::IpAddr>,
+    pub(crate) ip_version: IpVersion,
+    pub(crate) connect_retries: u32,
+    pub(crate) pool_max_idle_per_host: Option<usize>,
+    pub(crate) allow_truncated_body: bool,
+    pub(crate) max_response_size: Option<u64>,
+    pub(crate) pinned_public_key: Option<[u8; 32]>,
+    pub(crate) auto_decompress: bool,
+    pub(crate) shared_session: bool,
+    pub(crate) proxy_credentials: Option<(String, String)>,
+    pub(crate) proxy_from_env: bool,
+    pub(crate) retry: Option<RetryPolicy>,
+    #[cfg(feature = "gzip")]
+    pub(crate) compress_threshold: Option<usize>,
+}
+
+impl ClientBuilder {
+    /// Set the timeout that will be applied to every request sent by the
+    /// built client.
+    ///
+    /// A request that times out fails with an [`std::io::Error`] of
+    /// [`std::io::ErrorKind::TimedOut`] on all platforms, whether it happens
+    /// before the response or while reading the body.
+    ///
+    /// A request can opt out of it by calling
+    /// [`crate::prelude::CommonRequest::no_timeout`].
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    /// An alias of [`ClientBuilder::default_timeout`], so the client doesn't
+    /// need to be mutable to call
+    /// [`crate::prelude::CommonClient::set_timeout`] after built.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.default_timeout(timeout)
+    }
+
+    /// Reject connections that negotiate a TLS version lower than `version`.
+    ///
+    /// With the `isahc` feature, building the client fails with an
+    /// [`std::io::ErrorKind::Unsupported`] error, as isahc can't set it.
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls_version = Some(version);
+        self
+    }
+
+    /// Set the size of the buffer used to transfer the body of every request
+    /// and response. A larger buffer can reduce the callback overhead of high
+    /// throughput transfers.
+    ///
+    /// On Windows it will be clamped to at least 8KB, as recommended by
+    /// WinHTTP. Currently has no effect on Unix, where curl manages its own
+    /// buffers.
+    pub fn buffer_size(mut self, bytes: usize) -> Self {
+        self.buffer_size = Some(bytes);
+        self
+    }
+
+    /// Only speak HTTP/1.1, never negotiate HTTP/2 with the server.
+    ///
+    /// Useful for servers or proxies that misbehave with HTTP/2.
+    pub fn http1_only(mut self) -> Self {
+        self.http1_only = true;
+        self
+    }
+
+    /// Set the interval of the keepalive pings sent on idle HTTP/2
+    /// connections, `None` disables them. Defaults to 15s.
+    ///
+    /// Longer intervals suit flaky or metered links, shorter ones keep
+    /// long-polling connections from being dropped by middleboxes. Currently
+    /// only has effect on Windows.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use alhc::get_client_builder;
+    ///
+    /// let builder = get_client_builder().http2_keepalive(Some(Duration::from_secs(60)));
+    /// ```
+    pub fn http2_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.http2_keepalive = Some(interval);
+        self
+    }
+
+    /// Limit the client to send at most `requests_per_second` requests, the
+    /// requests are spaced out evenly and wait for their turn before being
+    /// sent. Zero disables the limit.
+    ///
+    /// When a response is `429 Too Many Requests` or `503 Service
+    /// Unavailable` with a `Retry-After` header in seconds, the following
+    /// requests will also wait for that long.
+    pub fn rate_limit(mut self, requests_per_second: u32) -> Self {
+        self.rate_limit = Some(requests_per_second).filter(|x| *x > 0);
+        self
+    }
+
+    /// Send the requests from `addr`, to choose the outbound interface on
+    /// hosts with several networks.
+    ///
+    /// Building the client fails if `addr` isn't assigned to this host. It's
+    /// not supported by WinHTTP, so building the client always fails on
+    /// Windows.
+    pub fn local_address(mut self, addr: std::net::IpAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Only connect to the server over the IP version, to work around a
+    /// broken IPv4 or IPv6 network when the host has both addresses.
+    ///
+    /// WinHTTP can't restrict the IP version, so building the client fails
+    /// on Windows unless it's [`IpVersion::Auto`].
+    pub fn ip_version(mut self, version: IpVersion) -> Self {
+        self.ip_version = version;
+        self
+    }
+
+    /// Limit the client to have at most `requests` requests in flight at the
+    /// same time, the others wait for their turn before being sent. Zero
+    /// disables the limit.
+    ///
+    /// A request holds its turn until its response has been read to the end
+    /// or dropped.
+    pub fn max_concurrent(mut self, requests: usize) -> Self {
+        self.max_concurrent = Some(requests).filter(|x| *x > 0);
+        self
+    }
+
+    /// Keep at most `max` idle connections to each host alive for the later
+    /// requests, `0` closes every connection once its request is done.
+    ///
+    /// As neither WinHTTP nor curl can limit the idle connections of a host,
+    /// a request made while `max` other requests to the same host are in
+    /// flight closes its connection once it's done, so at most `max` of them
+    /// are left idle afterwards.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Retry up to `retries` times with a short backoff when connecting to
+    /// the server fails, like when the connection is refused.
+    ///
+    /// Only the connection establishment is retried, before any of the body
+    /// has been sent, so it's safe for all methods.
+    ///
+    /// On Windows, an idempotent request failing on a kept-alive connection
+    /// the server has closed is sent once more on a new connection, even
+    /// without connect retries, which counts as one of them.
+    pub fn connect_retries(mut self, retries: u32) -> Self {
+        self.connect_retries = retries;
+        self
+    }
+
+    /// Accept bodies that end before the size in their `Content-Length`
+    /// header, instead of failing the read with
+    /// [`std::io::ErrorKind::UnexpectedEof`].
+    pub fn allow_truncated_body(mut self) -> Self {
+        self.allow_truncated_body = true;
+        self
+    }
+
+    /// Ask for compressed responses with `Accept-Encoding` and decode the
+    /// gzip and deflate bodies transparently, by WinHTTP on Windows and by
+    /// curl on Unix.
+    ///
+    /// The headers are kept as sent by the server, so `Content-Length` and
+    /// `Content-Encoding` still describe the compressed body.
+    pub fn auto_decompress(mut self) -> Self {
+        self.auto_decompress = true;
+        self
+    }
+
+    /// Fail reading a body larger than `bytes` with
+    /// [`std::io::ErrorKind::InvalidData`], instead of letting an untrusted
+    /// server stream an unbounded body into memory.
+    ///
+    /// A body whose `Content-Length` is larger than the limit fails on the
+    /// first read, the others once the limit is crossed.
+    pub fn max_response_size(mut self, bytes: u64) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Send idempotent requests again on transient failures following
+    /// `policy`, see [`RetryPolicy`] for which requests can be retried.
+    ///
+    /// The retries happen inside the request future, until the response
+    /// headers are received, reading the body is never retried.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Only accept HTTPS servers whose certificate has a public key matching
+    /// `spki_sha256`, the SHA-256 hash of its DER encoded
+    /// `SubjectPublicKeyInfo`, on top of the usual certificate validation.
+    ///
+    /// The hash can be computed with `openssl x509 -pubkey -noout | openssl
+    /// pkey -pubin -outform der | openssl dgst -sha256`. A mismatch fails the
+    /// request with [`crate::Error::Tls`] of
+    /// [`crate::TlsErrorReason::PinnedKeyMismatch`] before anything is sent.
+    /// Not supported by `isahc`, so building the client fails on Unix with
+    /// the `isahc` feature.
+    pub fn pin_public_key(mut self, spki_sha256: [u8; 32]) -> Self {
+        self.pinned_public_key = Some(spki_sha256);
+        self
+    }
+
+    /// Authenticate to the proxy with a user name and a password.
+    ///
+    /// On Windows a request answers the challenge of a `407 Proxy
+    /// Authentication Required` response by sending the request again once,
+    /// which only works for requests without a body. On Unix curl answers the
+    /// challenges by itself.
+    pub fn proxy_auth(mut self, user: &str, pass: &str) -> Self {
+        self.proxy_credentials = Some((user.to_owned(), pass.to_owned()));
+        self
+    }
+
+    /// Use the proxies set by the `HTTP_PROXY` and `HTTPS_PROXY` environment
+    /// variables, skipping the hosts listed in `NO_PROXY`, like curl and most
+    /// command line tools do. The lowercase names are also accepted.
+    ///
+    /// On Windows it replaces the system proxy settings of the WinHTTP
+    /// session.
+    pub fn proxy_from_env(mut self) -> Self {
+        self.proxy_from_env = true;
+        self
+    }
+
+    /// Build the client on a WinHTTP session shared by all the clients built
+    /// with this option, instead of opening a new one, which saves resources
+    /// for apps that build several clients.
+    ///
+    /// Each client still keeps its own connections. The shared session stays
+    /// open until the process exits, and the session wide options like
+    /// [`ClientBuilder::default_timeout`], [`ClientBuilder::min_tls_version`],
+    /// [`ClientBuilder::http1_only`], [`ClientBuilder::http2_keepalive`] and
+    /// [`ClientBuilder::proxy_from_env`] are applied to it, so they affect
+    /// every client sharing it.
+    ///
+    /// Has no effect on Unix, where all the clients already share one curl
+    /// multi handle.
+    pub fn shared_session(mut self, shared: bool) -> Self {
+        self.shared_session = shared;
+        self
+    }
+
+    /// Compress request bodies with gzip and set `Content-Encoding: gzip`
+    /// when they are at least `threshold` bytes, as small bodies aren't
+    /// worth compressing.
+    ///
+    /// Only the bodies provided by
+    /// [`crate::prelude::CommonRequest::body_bytes`] and
+    /// [`crate::prelude::CommonRequest::body_string`] will be compressed.
+    #[cfg(feature = "gzip")]
+    pub fn auto_compress_request(mut self, threshold: usize) -> Self {
+        self.compress_threshold = Some(threshold);
+        self
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    io::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use futures_lite::{io::Cursor, AsyncRead};
+
+use crate::{prelude::CommonRequest, retry::ReplayBody};
+
+/// Compress the whole data with gzip in memory.
+pub(crate) fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Set the body of the request, compressing it with gzip if it's not smaller
+/// than `threshold`.
+pub(crate) fn body_bytes_compressed<R: CommonRequest>(
+    req: R,
+    threshold: Option<usize>,
+    body: Vec<u8>,
+) -> R {
+    if let Some(threshold) = threshold {
+        if body.len() >= threshold {
+            if let Ok(compressed) = gzip(&body) {
+                let len = compressed.len();
+                return req
+                    .replace_header("Content-Encoding", "gzip")
+                    .body(Cursor::new(compressed), len);
+            }
+        }
+    }
+    let len = body.len();
+    req.body(Cursor::new(body), len)
+}
+
+/// Set the body of the request compressed with gzip, at once like the bodies
+/// over the compression threshold if it's already buffered so its size is
+/// still known, or while it's sent with chunked transfer otherwise. An empty
+/// body is still sent as a gzip stream.
+///
+/// The request must not compress the body it's given again.
+pub(crate) fn set_gzip_body<R: CommonRequest>(
+    req: R,
+    body: impl AsyncRead + Unpin + Send + Sync + 'static,
+) -> R {
+    match ReplayBody::from_body(body) {
+        Ok(data) => body_bytes_compressed(req, Some(0), data.as_ref().to_vec()),
+        Err(body) => req.body_chunked(GzipReader::new(body)),
+    }
+}
+
+/// A reader compressing the data of another reader with gzip as it's read.
+pub(crate) struct GzipReader {
+    inner: Box<dyn AsyncRead + Unpin + Send + Sync + 'static>,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    input: Box<[u8]>,
+    output: Vec<u8>,
+    output_pos: usize,
+}
+
+impl GzipReader {
+    pub(crate) fn new(inner: impl AsyncRead + Unpin + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Box::new(inner),
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+            input: vec![0; 16 * 1024].into_boxed_slice(),
+            output: Vec::new(),
+            output_pos: 0,
+        }
+    }
+}
+
+impl AsyncRead for GzipReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            // Hand out what has been compressed first
+            if this.output_pos < this.output.len() {
+                let size = buf.len().min(this.output.len() - this.output_pos);
+                buf[..size].copy_from_slice(&this.output[this.output_pos..this.output_pos + size]);
+                this.output_pos += size;
+                return Poll::Ready(Ok(size));
+            }
+            let Some(encoder) = &mut this.encoder else {
+                return Poll::Ready(Ok(0));
+            };
+            match Pin::new(&mut this.inner).poll_read(cx, &mut this.input) {
+                Poll::Ready(Ok(0)) => {
+                    // Write the rest of the stream and the gzip footer
+                    this.output = this.encoder.take().unwrap().finish()?;
+                }
+                Poll::Ready(Ok(size)) => {
+                    encoder.write_all(&this.input[..size])?;
+                    this.output = std::mem::take(encoder.get_mut());
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+            this.output_pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use futures_lite::AsyncReadExt;
+
+    use super::*;
+
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut decoded).unwrap();
+        decoded
+    }
+
+    fn gzip_reader(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let mut reader = GzipReader::new(Cursor::new(data.to_vec()));
+        futures_lite::future::block_on(reader.read_to_end(&mut compressed)).unwrap();
+        compressed
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = (0..100_000).map(|x| (x % 251) as u8).collect();
+        assert_eq!(gunzip(&gzip(&data).unwrap()), data);
+        assert_eq!(gunzip(&gzip_reader(&data)), data);
+    }
+
+    #[test]
+    fn empty_body_is_a_gzip_stream() {
+        let compressed = gzip(&[]).unwrap();
+        assert!(!compressed.is_empty());
+        assert!(gunzip(&compressed).is_empty());
+        let compressed = gzip_reader(&[]);
+        assert!(!compressed.is_empty());
+        assert!(gunzip(&compressed).is_empty());
+    }
+}
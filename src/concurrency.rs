@@ -0,0 +1,238 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// Limits how many requests of a client can be in flight at the same time,
+/// like an async semaphore.
+#[derive(Debug)]
+pub(crate) struct ConcurrencyLimiter {
+    max: usize,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    active: usize,
+    next_id: usize,
+    /// The ids of the waiters in the order they came, the ones which have
+    /// given up are skipped when a slot is released.
+    queue: VecDeque<usize>,
+    waiters: HashMap<usize, Waker>,
+    /// The waiters a released slot has been handed over to, which haven't
+    /// taken it yet.
+    granted: Vec<usize>,
+}
+
+impl ConcurrencyLimiter {
+    pub(crate) fn new(max: usize) -> Self {
+        Self {
+            max,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Hand the slot over to the first waiter, so a new request can't take
+    /// it first, or free it if there's none.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(id) = state.queue.pop_front() {
+            if let Some(waker) = state.waiters.remove(&id) {
+                state.granted.push(id);
+                drop(state);
+                waker.wake();
+                return;
+            }
+        }
+        state.active -= 1;
+    }
+}
+
+/// A slot of the limiter, released when dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// The waiting state of a request for its slot.
+#[derive(Debug)]
+pub(crate) struct PermitWait {
+    limiter: Arc<ConcurrencyLimiter>,
+    id: Option<usize>,
+}
+
+impl PermitWait {
+    pub(crate) fn new(limiter: Arc<ConcurrencyLimiter>) -> Self {
+        Self { limiter, id: None }
+    }
+
+    pub(crate) fn poll_acquire(&mut self, cx: &mut Context<'_>) -> Poll<Permit> {
+        let mut state = self.limiter.state.lock().unwrap();
+        match self.id {
+            Some(id) => {
+                if let Some(index) = state.granted.iter().position(|x| *x == id) {
+                    state.granted.swap_remove(index);
+                    self.id = None;
+                    return Poll::Ready(Permit {
+                        limiter: self.limiter.clone(),
+                    });
+                }
+                if let Some(waker) = state.waiters.get_mut(&id) {
+                    if !waker.will_wake(cx.waker()) {
+                        *waker = cx.waker().clone();
+                    }
+                }
+            }
+            None if state.active < self.limiter.max => {
+                state.active += 1;
+                return Poll::Ready(Permit {
+                    limiter: self.limiter.clone(),
+                });
+            }
+            None => {
+                let id = state.next_id;
+                state.next_id = state.next_id.wrapping_add(1);
+                state.queue.push_back(id);
+                state.waiters.insert(id, cx.waker().clone());
+                self.id = Some(id);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for PermitWait {
+    fn drop(&mut self) {
+        let Some(id) = self.id else {
+            return;
+        };
+        let mut state = self.limiter.state.lock().unwrap();
+        if state.waiters.remove(&id).is_some() {
+            return;
+        }
+        // The slot has been handed over but isn't taken, pass it on
+        if let Some(index) = state.granted.iter().position(|x| *x == id) {
+            state.granted.swap_remove(index);
+            drop(state);
+            self.limiter.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::Wake,
+    };
+
+    use super::*;
+
+    /// Counts how many times it has been woken up.
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn waiter(limiter: &Arc<ConcurrencyLimiter>) -> (PermitWait, Arc<CountingWaker>) {
+        (PermitWait::new(limiter.clone()), Arc::default())
+    }
+
+    fn poll(wait: &mut PermitWait, waker: &Arc<CountingWaker>) -> Poll<Permit> {
+        wait.poll_acquire(&mut Context::from_waker(&Waker::from(waker.clone())))
+    }
+
+    #[test]
+    fn release_wakes_the_first_waiter_only() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(1));
+        let (mut first, first_waker) = waiter(&limiter);
+        let permit = poll(&mut first, &first_waker);
+        assert!(permit.is_ready());
+
+        let mut waiters: Vec<_> = (0..4).map(|_| waiter(&limiter)).collect();
+        for (wait, waker) in &mut waiters {
+            assert!(poll(wait, waker).is_pending());
+        }
+
+        drop(permit);
+        let wakes: Vec<_> = waiters
+            .iter()
+            .map(|(_, waker)| waker.0.load(Ordering::SeqCst))
+            .collect();
+        assert_eq!(wakes, [1, 0, 0, 0]);
+
+        // A newcomer can't take the slot handed over
+        let (mut newcomer, newcomer_waker) = waiter(&limiter);
+        assert!(poll(&mut newcomer, &newcomer_waker).is_pending());
+
+        let (wait, waker) = &mut waiters[0];
+        assert!(poll(wait, waker).is_ready());
+    }
+
+    #[test]
+    fn dropped_waiters_pass_the_slot_on() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(1));
+        let (mut first, first_waker) = waiter(&limiter);
+        let permit = poll(&mut first, &first_waker);
+        let (mut second, second_waker) = waiter(&limiter);
+        let (mut third, third_waker) = waiter(&limiter);
+        let (mut fourth, fourth_waker) = waiter(&limiter);
+        assert!(poll(&mut second, &second_waker).is_pending());
+        assert!(poll(&mut third, &third_waker).is_pending());
+        assert!(poll(&mut fourth, &fourth_waker).is_pending());
+
+        // Gave up before its turn
+        drop(second);
+        drop(permit);
+        assert_eq!(third_waker.0.load(Ordering::SeqCst), 1);
+        // Gave up after the slot was handed over
+        drop(third);
+        assert_eq!(fourth_waker.0.load(Ordering::SeqCst), 1);
+        let permit = poll(&mut fourth, &fourth_waker);
+        assert!(permit.is_ready());
+
+        drop(permit);
+        assert_eq!(limiter.state.lock().unwrap().active, 0);
+    }
+
+    #[test]
+    fn never_exceeds_the_limit() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(3));
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..16)
+            .map(|_| {
+                let (limiter, active, peak) = (limiter.clone(), active.clone(), peak.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        let mut wait = PermitWait::new(limiter.clone());
+                        let permit =
+                            futures_lite::future::block_on(futures_lite::future::poll_fn(|cx| {
+                                wait.poll_acquire(cx)
+                            }));
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        std::thread::yield_now();
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        drop(permit);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+}
@@ -0,0 +1,438 @@
+use std::{
+    future::Future,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::Poll,
+};
+
+use crate::{
+    prelude::*,
+    response::{find_header, truncated_body_error, ContentRange},
+    Client, DynResult,
+};
+
+/// Options of [`Client::download_to_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadOptions {
+    /// How many ranged requests the body is split into, which are sent at the
+    /// same time.
+    pub chunks: usize,
+    /// How many times a failed chunk is downloaded again before giving up.
+    pub retries: u32,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            chunks: 4,
+            retries: 3,
+        }
+    }
+}
+
+impl Client {
+    /// Download the body of `url` into a file created at `path`, split into
+    /// ranged requests sent at the same time, and return the size of the body.
+    ///
+    /// The size of the body is asked with a `HEAD` request first. If it's
+    /// unknown or the server doesn't send `Accept-Ranges: bytes`, the body is
+    /// downloaded by a single request instead.
+    ///
+    /// The body is saved to a temporary file next to `path`, like
+    /// `file.bin.download`, which replaces `path` once it's complete. Each
+    /// chunk is saved to its own temporary file too, like `file.bin.part0`,
+    /// and they're concatenated once all of them are done. On failure, the
+    /// temporary files are removed, and a file already at `path` is kept.
+    pub async fn download_to_file(
+        &self,
+        url: &str,
+        path: impl AsRef<Path>,
+        options: DownloadOptions,
+    ) -> DynResult<u64> {
+        let path = path.as_ref();
+        let temp = temp_path(path, "download");
+        let result = async {
+            let size = match self.ranged_size(url).await {
+                Some(size) if options.chunks > 1 && size >= options.chunks as u64 => {
+                    self.download_chunks(url, &temp, size, options).await?
+                }
+                _ => retry(options.retries, || self.download_whole(url, &temp)).await?,
+            };
+            async_fs::rename(&temp, path).await?;
+            DynResult::Ok(size)
+        }
+        .await;
+        if result.is_err() {
+            let _ = async_fs::remove_file(&temp).await;
+        }
+        result
+    }
+
+    /// Resume downloading the body of `url` into the file at `path`, by only
+    /// asking for the bytes after the ones already in the file, and return
+    /// the size of the whole file.
+    ///
+    /// The file is created if it doesn't exist. If the server doesn't support
+    /// ranges and sends the whole body instead, the file is truncated and
+    /// downloaded again from the start.
+    pub async fn resume_download(&self, url: &str, path: impl AsRef<Path>) -> DynResult<u64> {
+        let path = path.as_ref();
+        let existing = match async_fs::metadata(path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        if existing == 0 {
+            return self.download_whole(url, path).await;
+        }
+        let res = self.get(url)?.range(existing, None).await?;
+        let content_range =
+            find_header(res.headers(), "Content-Range").and_then(ContentRange::parse);
+        let complete_length = content_range.as_ref().and_then(|x| x.complete_length());
+        match res.status_code() {
+            // Nothing is missing
+            416 if complete_length == Some(existing) => Ok(existing),
+            // The file doesn't match the body anymore, like when it's larger
+            416 => {
+                drop(res);
+                self.download_whole(url, path).await
+            }
+            206 => {
+                let start = content_range.and_then(|x| x.range()).map(|x| *x.start());
+                if start != Some(existing) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "the server didn't respond with the requested range",
+                    )
+                    .into());
+                }
+                let mut file = async_fs::OpenOptions::new().append(true).open(path).await?;
+                let size = existing + res.copy_to(&mut file).await?;
+                file.sync_all().await?;
+                match complete_length {
+                    Some(expected) if size != expected => {
+                        Err(truncated_body_error(size, expected).into())
+                    }
+                    _ => Ok(size),
+                }
+            }
+            _ => Ok(res.error_for_status()?.save(path).await?),
+        }
+    }
+
+    /// The size of the body if it can be downloaded in ranges.
+    async fn ranged_size(&self, url: &str) -> Option<u64> {
+        let res = self.head(url).ok()?.await.ok()?;
+        let accept_ranges = find_header(res.headers(), "Accept-Ranges")
+            .map(|x| x.split(',').any(|x| x.trim().eq_ignore_ascii_case("bytes")))
+            .unwrap_or(false);
+        if !res.is_success() || !accept_ranges {
+            return None;
+        }
+        res.content_length()
+    }
+
+    async fn download_whole(&self, url: &str, path: &Path) -> DynResult<u64> {
+        let res = self.get(url)?.await?.error_for_status()?;
+        Ok(res.save(path).await?)
+    }
+
+    async fn download_chunks(
+        &self,
+        url: &str,
+        path: &Path,
+        size: u64,
+        options: DownloadOptions,
+    ) -> DynResult<u64> {
+        let chunk_size = size / options.chunks as u64;
+        let parts: Vec<(PathBuf, RangeInclusive<u64>)> = (0..options.chunks)
+            .map(|i| {
+                let start = i as u64 * chunk_size;
+                // The last chunk also takes the remainder
+                let end = if i == options.chunks - 1 {
+                    size - 1
+                } else {
+                    start + chunk_size - 1
+                };
+                (temp_path(path, &format!("part{}", i)), start..=end)
+            })
+            .collect();
+        let result = async {
+            try_join_all(parts.iter().map(|(part, range)| {
+                retry(options.retries, move || {
+                    self.download_chunk(url, part, range.clone())
+                })
+            }))
+            .await?;
+            concat(path, parts.iter().map(|(part, _)| part.as_path())).await?;
+            DynResult::Ok(size)
+        }
+        .await;
+        for (part, _) in &parts {
+            let _ = async_fs::remove_file(part).await;
+        }
+        result
+    }
+
+    async fn download_chunk(
+        &self,
+        url: &str,
+        path: &Path,
+        range: RangeInclusive<u64>,
+    ) -> DynResult<()> {
+        let res = self
+            .get(url)?
+            .range(*range.start(), Some(*range.end()))
+            .await?
+            .error_for_status()?;
+        let content_range =
+            find_header(res.headers(), "Content-Range").and_then(ContentRange::parse);
+        if res.status_code() != 206 || content_range.and_then(|x| x.range()) != Some(range.clone())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the server didn't respond with the requested range",
+            )
+            .into());
+        }
+        let expected = range.end() - range.start() + 1;
+        let size = res.save(path).await?;
+        if size < expected {
+            return Err(truncated_body_error(size, expected).into());
+        }
+        if size > expected {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the server sent more than the requested range",
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// A temporary file next to `path`, named after it with `extension` added.
+fn temp_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+/// Run the job again until it succeeds or has been retried `retries` times.
+/// Client errors like `404 Not Found` won't change, so they're returned
+/// right away.
+async fn retry<T, F, Fut>(retries: u32, mut job: F) -> DynResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = DynResult<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        match job().await {
+            Err(err) if attempts < retries && !is_client_error(&err) => attempts += 1,
+            result => return result,
+        }
+    }
+}
+
+fn is_client_error(err: &crate::DynError) -> bool {
+    matches!(
+        err.downcast_ref::<crate::Error>(),
+        Some(crate::Error::Status { code, .. }) if (400..500).contains(code)
+    )
+}
+
+/// Drive the futures at the same time until all of them succeed or one of
+/// them fails, the others are dropped then.
+async fn try_join_all<F>(futures: impl IntoIterator<Item = F>) -> DynResult<()>
+where
+    F: Future<Output = DynResult<()>>,
+{
+    let mut futures: Vec<Option<Pin<Box<F>>>> =
+        futures.into_iter().map(|x| Some(Box::pin(x))).collect();
+    futures_lite::future::poll_fn(|cx| {
+        let mut pending = false;
+        for slot in futures.iter_mut() {
+            let Some(future) = slot else {
+                continue;
+            };
+            match future.as_mut().poll(cx) {
+                Poll::Ready(Ok(())) => *slot = None,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => pending = true,
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    })
+    .await
+}
+
+/// Write the parts one after another into a file created at `path`.
+async fn concat(path: &Path, parts: impl Iterator<Item = &Path>) -> std::io::Result<()> {
+    let mut file = async_fs::File::create(path).await?;
+    for part in parts {
+        let part = async_fs::File::open(part).await?;
+        futures_lite::io::copy(part, &mut file).await?;
+    }
+    file.sync_all().await
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    const BODY: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+    /// Serve `BODY`, honouring the `Range` header only if `ranges` is set.
+    fn body_server(ranges: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut head = Vec::new();
+                let mut buf = [0; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => head.push(buf[0]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head).to_lowercase();
+                let range = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .filter(|_| ranges)
+                    .and_then(|range| range.split_once('-'))
+                    .map(|(start, end)| {
+                        let start: usize = start.parse().unwrap();
+                        let end = end.parse().unwrap_or(BODY.len() - 1);
+                        start..=end
+                    });
+                let (status, range) = match range {
+                    Some(range) => (
+                        format!(
+                            "206 Partial Content\r\nContent-Range: bytes {}-{}/{}",
+                            range.start(),
+                            range.end(),
+                            BODY.len()
+                        ),
+                        range,
+                    ),
+                    None => ("200 OK".to_owned(), 0..=BODY.len() - 1),
+                };
+                let body = if head.starts_with("head ") {
+                    &[][..]
+                } else {
+                    &BODY[range.clone()]
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    if ranges {
+                        "Accept-Ranges: bytes\r\n"
+                    } else {
+                        ""
+                    },
+                    range.count()
+                );
+                let _ = stream.write_all(body);
+            }
+        });
+        url
+    }
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("alhc-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn resume_with_a_range() {
+        let url = body_server(true);
+        let path = test_path("resume-range");
+        std::fs::write(&path, &BODY[..10]).unwrap();
+        let client = crate::get_client_builder().build().unwrap();
+        let size = futures_lite::future::block_on(client.resume_download(&url, &path)).unwrap();
+        assert_eq!(size, BODY.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), BODY);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_restarts_without_ranges() {
+        let url = body_server(false);
+        let path = test_path("resume-whole");
+        std::fs::write(&path, b"stale").unwrap();
+        let client = crate::get_client_builder().build().unwrap();
+        let size = futures_lite::future::block_on(client.resume_download(&url, &path)).unwrap();
+        assert_eq!(size, BODY.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), BODY);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn download_in_chunks() {
+        let url = body_server(true);
+        let path = test_path("chunks");
+        let client = crate::get_client_builder().build().unwrap();
+        let size = futures_lite::future::block_on(client.download_to_file(
+            &url,
+            &path,
+            DownloadOptions {
+                chunks: 3,
+                retries: 0,
+            },
+        ))
+        .unwrap();
+        assert_eq!(size, BODY.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), BODY);
+        assert!(!temp_path(&temp_path(&path, "download"), "part0").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_download_keeps_the_file() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let _ = stream.write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            }
+        });
+        let path = std::env::temp_dir().join(format!("alhc-download-{}", std::process::id()));
+        std::fs::write(&path, "kept").unwrap();
+        let client = crate::get_client_builder().build().unwrap();
+        let result = futures_lite::future::block_on(client.download_to_file(
+            &url,
+            &path,
+            DownloadOptions::default(),
+        ));
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept");
+        assert!(!temp_path(&path, "download").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn temp_path_is_next_to_the_file() {
+        assert_eq!(
+            temp_path(Path::new("dir/file.bin"), "part0"),
+            Path::new("dir/file.bin.part0")
+        );
+    }
+}
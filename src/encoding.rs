@@ -0,0 +1,105 @@
+//! Percent-encoding of URL parts, following the encode sets of the
+//! [WHATWG URL standard](https://url.spec.whatwg.org/#percent-encoded-bytes).
+//!
+//! ```
+//! use alhc::encoding::{encode_path_segment, encode_query_component};
+//!
+//! assert_eq!(encode_query_component("a b&c=d"), "a%20b%26c%3Dd");
+//! assert_eq!(encode_path_segment("50%/€"), "50%25%2F%E2%82%AC");
+//! ```
+
+use std::fmt::Write;
+
+/// Encode a key or a value of a query string, like `key=value`, so `&`, `=`,
+/// `+` and `#` in it are kept as data.
+///
+/// Uses the component percent-encode set, so space becomes `%20` instead of
+/// `+`.
+pub fn encode_query_component(value: &str) -> String {
+    encode(value, |x| {
+        in_userinfo_set(x) || matches!(x, b'$' | b'%' | b'&' | b'+' | b',')
+    })
+}
+
+/// Encode a single segment of a URL path, so `/` in it doesn't split the
+/// path.
+///
+/// Uses the path percent-encode set, along with `/`, `\` and `%`.
+pub fn encode_path_segment(value: &str) -> String {
+    encode(value, |x| {
+        in_path_set(x) || matches!(x, b'/' | b'\\' | b'%')
+    })
+}
+
+fn encode(value: &str, in_set: impl Fn(u8) -> bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        // Non-ASCII characters are encoded as their UTF-8 bytes
+        if !byte.is_ascii() || byte.is_ascii_control() || in_set(byte) {
+            let _ = write!(encoded, "%{:02X}", byte);
+        } else {
+            encoded.push(byte as char);
+        }
+    }
+    encoded
+}
+
+fn in_query_set(byte: u8) -> bool {
+    matches!(byte, b' ' | b'"' | b'#' | b'<' | b'>')
+}
+
+fn in_path_set(byte: u8) -> bool {
+    in_query_set(byte) || matches!(byte, b'?' | b'^' | b'`' | b'{' | b'}')
+}
+
+fn in_userinfo_set(byte: u8) -> bool {
+    in_path_set(byte)
+        || matches!(
+            byte,
+            b'/' | b':' | b';' | b'=' | b'@' | b'[' | b'\\' | b']' | b'|'
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_components() {
+        for (value, encoded) in [
+            ("", ""),
+            ("AZaz09-._~", "AZaz09-._~"),
+            ("a b", "a%20b"),
+            ("!*'()", "!*'()"),
+            (":/?#[]@", "%3A%2F%3F%23%5B%5D%40"),
+            ("$&+,;=", "%24%26%2B%2C%3B%3D"),
+            ("\"<>\\^`{|}", "%22%3C%3E%5C%5E%60%7B%7C%7D"),
+            ("100%", "100%25"),
+            ("\0\n\x7f", "%00%0A%7F"),
+            ("€", "%E2%82%AC"),
+            ("日本", "%E6%97%A5%E6%9C%AC"),
+        ] {
+            assert_eq!(encode_query_component(value), encoded, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn path_segments() {
+        for (value, encoded) in [
+            ("", ""),
+            ("AZaz09-._~", "AZaz09-._~"),
+            ("a b", "a%20b"),
+            // Sub-delimiters, `:` and `@` are allowed in a segment
+            ("!$&'()*+,;=:@", "!$&'()*+,;=:@"),
+            ("a/b\\c", "a%2Fb%5Cc"),
+            ("?#", "%3F%23"),
+            ("\"<>`{}", "%22%3C%3E%60%7B%7D"),
+            ("a^b", "a%5Eb"),
+            ("50%", "50%25"),
+            ("\t\x7f", "%09%7F"),
+            ("café", "caf%C3%A9"),
+        ] {
+            assert_eq!(encode_path_segment(value), encoded, "{value:?}");
+        }
+    }
+}
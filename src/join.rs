@@ -0,0 +1,134 @@
+use crate::{DynError, DynResult};
+
+/// A summary of a batch of requests, keeping every result along with the
+/// url it was sent to.
+///
+/// It can be collected from any iterator of `(url, result)` pairs, so the
+/// output of something like `join_all` can be tallied in one go.
+#[derive(Debug)]
+pub struct JoinResults<T> {
+    successes: Vec<(String, T)>,
+    failures: Vec<(String, DynError)>,
+}
+
+impl<T> Default for JoinResults<T> {
+    fn default() -> Self {
+        Self {
+            successes: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+impl<T> JoinResults<T> {
+    /// Record the result of a request sent to `url`.
+    pub fn push(&mut self, url: impl Into<String>, result: DynResult<T>) {
+        match result {
+            Ok(value) => self.successes.push((url.into(), value)),
+            Err(err) => self.failures.push((url.into(), err)),
+        }
+    }
+
+    /// Amount of requests in the batch.
+    pub fn total_count(&self) -> usize {
+        self.successes.len() + self.failures.len()
+    }
+
+    /// Amount of requests that succeeded.
+    pub fn success_count(&self) -> usize {
+        self.successes.len()
+    }
+
+    /// Amount of requests that failed.
+    pub fn failure_count(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// Whether every request in the batch succeeded.
+    pub fn all_succeeded(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The successful results with their urls, in the order they were pushed.
+    pub fn successes(&self) -> &[(String, T)] {
+        &self.successes
+    }
+
+    /// The errors with their urls, in the order they were pushed.
+    pub fn failures(&self) -> &[(String, DynError)] {
+        &self.failures
+    }
+
+    /// The urls of all failed requests.
+    pub fn failed_urls(&self) -> impl Iterator<Item = &str> {
+        self.failures.iter().map(|(url, _)| url.as_str())
+    }
+}
+
+impl<T, U: Into<String>> FromIterator<(U, DynResult<T>)> for JoinResults<T> {
+    fn from_iter<I: IntoIterator<Item = (U, DynResult<T>)>>(iter: I) -> Self {
+        let mut results = Self::default();
+        results.extend(iter);
+        results
+    }
+}
+
+impl<T, U: Into<String>> Extend<(U, DynResult<T>)> for JoinResults<T> {
+    fn extend<I: IntoIterator<Item = (U, DynResult<T>)>>(&mut self, iter: I) {
+        for (url, result) in iter {
+            self.push(url, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(reason: &str) -> DynResult<u16> {
+        Err(std::io::Error::other(reason.to_owned()).into())
+    }
+
+    #[test]
+    fn mixed_batch() {
+        let results: JoinResults<u16> = vec![
+            ("https://example.com/a", Ok(200)),
+            ("https://example.com/b", failed("connection refused")),
+            ("https://example.com/c", Ok(404)),
+            ("https://example.com/d", failed("timed out")),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(results.total_count(), 4);
+        assert_eq!(results.success_count(), 2);
+        assert_eq!(results.failure_count(), 2);
+        assert!(!results.all_succeeded());
+        assert_eq!(
+            results.failed_urls().collect::<Vec<_>>(),
+            ["https://example.com/b", "https://example.com/d"]
+        );
+        assert_eq!(results.failures()[1].1.to_string(), "timed out");
+        assert_eq!(
+            results.successes(),
+            [
+                ("https://example.com/a".to_owned(), 200),
+                ("https://example.com/c".to_owned(), 404)
+            ]
+        );
+    }
+
+    #[test]
+    fn extend_batch() {
+        let mut results = JoinResults::default();
+        assert!(results.all_succeeded());
+        assert_eq!(results.total_count(), 0);
+        results.extend([(String::from("https://example.com/"), Ok(()))]);
+        assert!(results.all_succeeded());
+        results.push(
+            "https://example.com/x",
+            Err(std::io::Error::other("x").into()),
+        );
+        assert_eq!(results.failure_count(), 1);
+        assert_eq!(results.total_count(), 2);
+    }
+}
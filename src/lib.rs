@@ -1,12 +1,40 @@
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod cancel;
 mod client;
+#[cfg(feature = "gzip")]
+mod compress;
+mod concurrency;
+#[cfg(feature = "fs")]
+mod download;
+pub mod encoding;
+mod error;
+mod join;
 mod method;
 pub mod prelude;
+mod proxy;
+mod rate_limit;
 mod response;
+mod retry;
+#[cfg(feature = "sse")]
+pub mod sse;
+mod stream;
+mod timer;
+mod trace;
+pub use cancel::CancelHandle;
 pub use client::*;
+#[cfg(feature = "fs")]
+pub use download::DownloadOptions;
+pub use error::*;
+pub use join::*;
 pub use method::*;
 pub use response::*;
+pub use retry::RetryPolicy;
+pub use stream::BytesStream;
+#[cfg(feature = "serde")]
+pub use stream::JsonLines;
 #[cfg(target_os = "windows")]
 pub mod windows;
 
@@ -17,10 +45,12 @@ pub mod unix;
 compile_error!("ALHC is currently not supported on your target os.");
 
 #[cfg(not(feature = "anyhow"))]
-pub type DynResult<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub type DynError = Box<dyn std::error::Error>;
 #[cfg(feature = "anyhow")]
-pub type DynResult<T = ()> = anyhow::Result<T>;
+pub type DynError = anyhow::Error;
 
-pub fn get_client_builder() -> impl prelude::CommonClientBuilder {
+pub type DynResult<T = ()> = std::result::Result<T, DynError>;
+
+pub fn get_client_builder() -> ClientBuilder {
     ClientBuilder::default()
 }
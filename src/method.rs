@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy)]
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Method {
     GET,
     POST,
@@ -9,6 +11,15 @@ pub enum Method {
     DELETE,
     CONNECT,
     OPTIONS,
+    // WebDAV (RFC 4918) and CalDAV (RFC 4791) extension methods
+    PROPFIND,
+    PROPPATCH,
+    MKCOL,
+    COPY,
+    MOVE,
+    LOCK,
+    UNLOCK,
+    REPORT,
 }
 
 impl Method {
@@ -23,9 +34,41 @@ impl Method {
             Method::DELETE => "DELETE",
             Method::CONNECT => "CONNECT",
             Method::OPTIONS => "OPTIONS",
+            Method::PROPFIND => "PROPFIND",
+            Method::PROPPATCH => "PROPPATCH",
+            Method::MKCOL => "MKCOL",
+            Method::COPY => "COPY",
+            Method::MOVE => "MOVE",
+            Method::LOCK => "LOCK",
+            Method::UNLOCK => "UNLOCK",
+            Method::REPORT => "REPORT",
         }
     }
 
+    /// Find the method by its name, which is case sensitive.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "GET" => Method::GET,
+            "POST" => Method::POST,
+            "HEAD" => Method::HEAD,
+            "PATCH" => Method::PATCH,
+            "PUT" => Method::PUT,
+            "TRACE" => Method::TRACE,
+            "DELETE" => Method::DELETE,
+            "CONNECT" => Method::CONNECT,
+            "OPTIONS" => Method::OPTIONS,
+            "PROPFIND" => Method::PROPFIND,
+            "PROPPATCH" => Method::PROPPATCH,
+            "MKCOL" => Method::MKCOL,
+            "COPY" => Method::COPY,
+            "MOVE" => Method::MOVE,
+            "LOCK" => Method::LOCK,
+            "UNLOCK" => Method::UNLOCK,
+            "REPORT" => Method::REPORT,
+            _ => return None,
+        })
+    }
+
     // For windows only
     #[cfg(target_os = "windows")]
     pub(crate) fn as_raw_str_wide(&self) -> *const u16 {
@@ -39,7 +82,110 @@ impl Method {
             Method::DELETE => &[68, 69, 76, 69, 84, 69, 0],
             Method::CONNECT => &[67, 79, 78, 78, 69, 67, 84, 0],
             Method::OPTIONS => &[79, 80, 84, 73, 79, 78, 83, 0],
+            Method::PROPFIND => &[80, 82, 79, 80, 70, 73, 78, 68, 0],
+            Method::PROPPATCH => &[80, 82, 79, 80, 80, 65, 84, 67, 72, 0],
+            Method::MKCOL => &[77, 75, 67, 79, 76, 0],
+            Method::COPY => &[67, 79, 80, 89, 0],
+            Method::MOVE => &[77, 79, 86, 69, 0],
+            Method::LOCK => &[76, 79, 67, 75, 0],
+            Method::UNLOCK => &[85, 78, 76, 79, 67, 75, 0],
+            Method::REPORT => &[82, 69, 80, 79, 82, 84, 0],
         };
         data.as_ptr()
     }
 }
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error returned when parsing a name that isn't a known [`Method`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMethodError {
+    name: String,
+}
+
+impl Display for ParseMethodError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown HTTP method {:?}", self.name)
+    }
+}
+
+impl std::error::Error for ParseMethodError {}
+
+impl FromStr for Method {
+    type Err = ParseMethodError;
+
+    /// Parse the name of a method case-insensitively, like `get`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::from_name(&name.to_ascii_uppercase()).ok_or_else(|| ParseMethodError {
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl TryFrom<&str> for Method {
+    type Error = ParseMethodError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [Method; 17] = [
+        Method::GET,
+        Method::POST,
+        Method::HEAD,
+        Method::PATCH,
+        Method::PUT,
+        Method::TRACE,
+        Method::DELETE,
+        Method::CONNECT,
+        Method::OPTIONS,
+        Method::PROPFIND,
+        Method::PROPPATCH,
+        Method::MKCOL,
+        Method::COPY,
+        Method::MOVE,
+        Method::LOCK,
+        Method::UNLOCK,
+        Method::REPORT,
+    ];
+
+    #[test]
+    fn parse_names() {
+        for method in METHODS {
+            assert_eq!(method.as_str().parse(), Ok(method));
+            assert_eq!(method.as_str().to_lowercase().parse(), Ok(method));
+            assert_eq!(Method::try_from(method.to_string().as_str()), Ok(method));
+        }
+        assert_eq!("pAtCh".parse(), Ok(Method::PATCH));
+    }
+
+    #[test]
+    fn unknown_names() {
+        for name in ["", "FETCH", " GET", "GET "] {
+            let err = name.parse::<Method>().unwrap_err();
+            assert_eq!(err.to_string(), format!("unknown HTTP method {name:?}"));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn wide_names() {
+        for method in METHODS {
+            let wide = method.as_raw_str_wide();
+            let wide = (0..)
+                .map(|i| unsafe { *wide.add(i) })
+                .take_while(|&x| x != 0)
+                .collect::<Vec<_>>();
+            assert_eq!(String::from_utf16(&wide).unwrap(), method.as_str());
+        }
+    }
+}
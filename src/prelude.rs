@@ -1,8 +1,13 @@
-use crate::{Method, ResponseBody};
+pub use crate::encoding::{encode_path_segment, encode_query_component};
+use crate::{CancelHandle, Method, ResponseBody};
 use core::future::Future;
 use core::time::Duration;
 use futures_lite::io::Cursor;
-use futures_lite::AsyncRead;
+use futures_lite::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::time::Instant;
 
 #[cfg(target_os = "windows")]
 pub type Request = crate::windows::WinHTTPRequest;
@@ -27,28 +32,266 @@ pub type Response = crate::unix::CURLResponse;
 ///
 /// [`CommonResponse`] provided some convenient methods can help you receive
 /// small data like text or JSON.
+///
+/// Since 0.3.0-alpha.5, implementations must also provide
+/// [`CommonRequest::body_chunked`] for streamed bodies,
+/// [`CommonRequest::cancellable`] for cancelling requests and, with the `gzip`
+/// feature, [`CommonRequest::gzip_body`]. The other methods added since then
+/// have a default implementation, which ignores the option or reports nothing
+/// when the implementation can't support it.
+#[cfg_attr(feature = "async_t", async_t::async_trait)]
+#[cfg_attr(not(feature = "async_t"), allow(async_fn_in_trait))]
 pub trait CommonRequest: Future
 where
     Self: Sized,
 {
     /// Provide data as a body in request
+    ///
+    /// The body is sent with `Content-Length: body_size`, so it must provide
+    /// exactly that many bytes, use [`CommonRequest::body_with_size`] if the
+    /// size may be unknown.
     fn body(self, body: impl AsyncRead + Unpin + Send + Sync + 'static, body_size: usize) -> Self;
+    /// Provide data of unknown length as a body in request
+    ///
+    /// The body will be sent with `Transfer-Encoding: chunked`, so some servers
+    /// which require `Content-Length` may reject it, use [`CommonRequest::body`]
+    /// if the size is known.
+    fn body_chunked(self, body: impl AsyncRead + Unpin + Send + Sync + 'static) -> Self;
+    /// Provide data as a body in request, whose size may be unknown, like the
+    /// one of a stream forwarded from another response.
+    ///
+    /// With `Some` size, it's sent with `Content-Length` like
+    /// [`CommonRequest::body`], otherwise it's sent with `Transfer-Encoding:
+    /// chunked` like [`CommonRequest::body_chunked`], on every platform.
+    fn body_with_size(
+        self,
+        body: impl AsyncRead + Unpin + Send + Sync + 'static,
+        body_size: Option<usize>,
+    ) -> Self {
+        match body_size {
+            Some(body_size) => self.body(body, body_size),
+            None => self.body_chunked(body),
+        }
+    }
+    /// Compress the body with gzip and send it with `Content-Encoding: gzip`,
+    /// for servers which accept compressed requests, both for the body
+    /// provided before and after.
+    ///
+    /// Buffered bodies like the ones of [`CommonRequest::body_bytes`] provided
+    /// after it are compressed at once and keep their `Content-Length`, other
+    /// bodies are compressed while they're sent with `Transfer-Encoding:
+    /// chunked`, as the compressed size is unknown up front. Without a body,
+    /// an empty gzip stream is sent.
+    #[cfg(feature = "gzip")]
+    fn gzip_body(self) -> Self;
     /// Provide string data as a body in request
     fn body_string(self, body: String) -> Self {
-        let len = body.len();
-        self.body(Cursor::new(body), len)
+        self.body_bytes(body.into_bytes())
     }
     /// Provide binary data as a body in request
     fn body_bytes(self, body: Vec<u8>) -> Self {
         let len = body.len();
         self.body(Cursor::new(body), len)
     }
+    /// Explicitly send an empty body with `Content-Length: 0`, unlike a
+    /// request without body provided, which sends no `Content-Length` at all.
+    ///
+    /// Useful for servers that require `Content-Length` on `POST` and `PUT`.
+    fn body_empty(self) -> Self {
+        self.body(futures_lite::io::empty(), 0)
+    }
+    /// Provide the content of a file as a body in request, the file will be
+    /// streamed instead of being loaded into memory.
+    ///
+    /// The file is opened before the request is sent, so a missing file will
+    /// be reported here, as well as a file too large for `usize` with
+    /// [`std::io::ErrorKind::InvalidInput`].
+    #[cfg(feature = "fs")]
+    async fn body_file(self, path: impl AsRef<Path>) -> crate::DynResult<Self> {
+        let file = async_fs::File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let len = usize::try_from(len).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "file is too large to be sent as a body",
+            )
+        })?;
+        Ok(self.body(file, len))
+    }
     /// Add a header value, will keep exists same header.
+    ///
+    /// A header with an invalid name or a value containing control characters
+    /// like CR or LF is not added, and the request will fail with
+    /// [`crate::Error::InvalidHeader`] instead of being sent.
     fn header(self, header: &str, value: &str) -> Self;
+    /// Add several header values at once, like the ones from a map, each of
+    /// them with [`CommonRequest::header`], so the same header given twice is
+    /// sent twice.
+    fn headers<'a>(self, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        headers
+            .into_iter()
+            .fold(self, |req, (header, value)| req.header(header, value))
+    }
     /// Replace a header value, add if not exists.
     fn replace_header(self, header: &str, value: &str) -> Self {
         self.header(header, value)
     }
+    /// Only get the body if it doesn't match the ETag of a cached copy, by
+    /// setting the `If-None-Match` header.
+    ///
+    /// The server responds with `304 Not Modified` and no body if it matches,
+    /// see [`ResponseBody::not_modified`].
+    fn if_none_match(self, etag: &str) -> Self {
+        self.replace_header("If-None-Match", etag)
+    }
+    /// Only get the body if it has changed since the date of a cached copy,
+    /// like `Wed, 21 Oct 2015 07:28:00 GMT`, by setting the
+    /// `If-Modified-Since` header.
+    ///
+    /// The server responds with `304 Not Modified` and no body if it hasn't,
+    /// see [`ResponseBody::not_modified`].
+    fn if_modified_since(self, date: &str) -> Self {
+        self.replace_header("If-Modified-Since", date)
+    }
+    /// Only get a part of the body, from the byte at `start` to the one at
+    /// `end` included, or to the end of the body if `end` is `None`, by
+    /// setting the `Range` header.
+    ///
+    /// The server responds with `206 Partial Content` and the part of the
+    /// body, or with `200 OK` and the whole body if it doesn't support
+    /// ranges, see [`ResponseBody::content_range`].
+    fn range(self, start: u64, end: Option<u64>) -> Self {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        self.replace_header("Range", &range)
+    }
+    /// Only get the last `len` bytes of the body, by setting the `Range`
+    /// header, see [`CommonRequest::range`].
+    fn range_suffix(self, len: u64) -> Self {
+        self.replace_header("Range", &format!("bytes=-{}", len))
+    }
+    /// Ask the server to accept the request before its body is sent, by
+    /// setting the `Expect: 100-continue` header, so a large upload the
+    /// server would refuse isn't sent for nothing.
+    ///
+    /// The body is sent once the server answers `100 Continue`, or after
+    /// waiting a second for it, as some servers never answer. If the server
+    /// rejects the request instead, like with `417 Expectation Failed`, the
+    /// body isn't sent and the rejection is the response.
+    ///
+    /// On Windows, WinHTTP doesn't wait before sending the body, so the
+    /// rejection only comes back once the body has been sent. With the
+    /// `isahc` feature, curl keeps sending the request again after rejected,
+    /// so the request fails instead.
+    fn expect_continue(self) -> Self {
+        self.replace_header("Expect", "100-continue")
+    }
+    /// Remove all the values of a header, including the ones the system
+    /// library would add by itself like `Accept`, so the header isn't sent.
+    ///
+    /// The default implementation replaces the header with an empty value.
+    fn remove_header(self, header: &str) -> Self {
+        self.replace_header(header, "")
+    }
+    /// Get the value of a header set on the request, like to build the
+    /// canonical request when signing it. The values of a header added
+    /// multiple times are joined with `, `.
+    ///
+    /// Only the headers set with the methods of the request are known, not
+    /// the ones the system library adds by itself when sending it.
+    ///
+    /// The default implementation doesn't know any header.
+    fn get_header(&self, _header: &str) -> Option<String> {
+        None
+    }
+    /// Fail the request with [`std::io::ErrorKind::TimedOut`] if it isn't
+    /// done by `at`, covering sending it, receiving the response head and
+    /// reading the whole body.
+    ///
+    /// Unlike the timeout of the client which applies to each phase, like
+    /// waiting for each chunk of the body, a slow body trickling in can't
+    /// keep the request going past the deadline.
+    ///
+    /// The default implementation ignores the deadline.
+    fn deadline(self, _at: Instant) -> Self {
+        self
+    }
+    /// Set the [`CommonRequest::deadline`] of the request `timeout` from now.
+    fn overall_timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+    /// Disable the client's default timeout for this request, useful for
+    /// known-long operations like large downloads.
+    ///
+    /// The default implementation keeps the timeout.
+    fn no_timeout(self) -> Self {
+        self
+    }
+    /// Attach a correlation tag to the request, like an id or the logical
+    /// name of the request, so errors of the request can tell which request
+    /// has failed.
+    ///
+    /// Errors of the request future will be wrapped in a
+    /// [`crate::TaggedError`] carrying the tag. With the `tracing` feature,
+    /// the tag is also recorded in the `request` span of the request.
+    ///
+    /// The default implementation ignores the tag.
+    fn tag(self, _tag: impl Into<String>) -> Self {
+        self
+    }
+    /// Answer the NTLM or Negotiate (Kerberos) challenge of a `401
+    /// Unauthorized` or `407 Proxy Authentication Required` response with
+    /// the credentials of the current Windows user, by sending the request
+    /// again once.
+    ///
+    /// Only requests without a body can be sent again, the others will get
+    /// the challenge response as is.
+    ///
+    /// The credentials are only sent to the hosts allowed by the default
+    /// autologon policy of WinHTTP, which are the intranet ones, so internet
+    /// hosts get the challenge response as is too.
+    ///
+    /// The default implementation doesn't answer the challenge.
+    #[cfg(target_os = "windows")]
+    fn windows_integrated_auth(self) -> Self {
+        self
+    }
+    /// Answer the Digest challenge of a `401 Unauthorized` response with the
+    /// user name and the password, by sending the request again once.
+    ///
+    /// The credentials are never sent with other schemes like Basic. On
+    /// Windows, only requests without a body can be sent again, the others
+    /// will get the challenge response as is.
+    ///
+    /// The default implementation doesn't answer the challenge.
+    fn digest_auth(self, _user: &str, _pass: &str) -> Self {
+        self
+    }
+    /// Make the request cancellable, returning a [`CancelHandle`] that can
+    /// abort the transfer while it's in flight.
+    ///
+    /// After cancelled, the request future or reading the response will
+    /// resolve to an [`std::io::ErrorKind::Interrupted`] error.
+    fn cancellable(self) -> (Self, CancelHandle);
+    /// Send the request, which is the same as awaiting the request itself, as
+    /// it's already the future resolving to the response.
+    ///
+    /// ```no_run
+    /// use alhc::prelude::*;
+    /// use alhc::*;
+    ///
+    /// # async fn run() -> DynResult {
+    /// let client = get_client_builder().build()?;
+    /// let res = client.get("https://httpbin.org/get")?.send().await?;
+    /// println!("{}", res.status_code());
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn send(self) -> Self {
+        self
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -70,24 +313,246 @@ impl<R: CommonRequest> CommonRequestSerdeExt for R {}
 /// All the response will implement [`AsyncRead`], which allows you to read data
 /// in chunks of bytes without load all in memory.
 ///
+/// Since 0.3.0-alpha.5, implementations must also provide
+/// [`CommonResponse::status_code`] and [`CommonResponse::headers`] for the
+/// response head before the body, and [`CommonResponse::timing`]. The other
+/// methods added since then have a default implementation.
 pub trait CommonResponse: AsyncRead
 where
     Self: Sized + Unpin,
 {
+    /// The status code of the response.
+    fn status_code(&self) -> u16;
+
+    /// The headers of the response, which are available before reading the
+    /// body.
+    ///
+    /// The values of a repeated header are joined with `, `, except for
+    /// `Set-Cookie` which only has its first value here, use
+    /// [`CommonResponse::recv`] and [`ResponseBody::headers_iter`] to get
+    /// every cookie.
+    fn headers(&self) -> &HashMap<String, String>;
+
+    /// The HTTP version the response has been received over, to check
+    /// whether HTTP/2 has been negotiated.
+    ///
+    /// The default implementation assumes HTTP/1.1.
+    fn http_version(&self) -> crate::HttpVersion {
+        crate::HttpVersion::Http11
+    }
+
+    /// How long the request took, the total time is only known once the body
+    /// has been read to the end.
+    fn timing(&self) -> crate::Timing;
+
+    /// The TLS version and cipher suite the connection of the response was
+    /// encrypted with, `None` if it isn't encrypted.
+    ///
+    /// On Unix it's only known with the `openssl` feature and curl built with
+    /// the same OpenSSL, and always `None` with the `isahc` feature, as
+    /// `isahc` doesn't expose it.
+    ///
+    /// The default implementation reports nothing.
+    fn tls_info(&self) -> Option<crate::TlsInfo> {
+        None
+    }
+
+    /// The size of the body in the `Content-Length` header, `None` if it's
+    /// absent or the body is chunked.
+    fn content_length(&self) -> Option<u64> {
+        crate::response::content_length(self.headers())
+    }
+
+    /// Whether the status code is `2xx`.
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code())
+    }
+
+    /// Whether the status code is `4xx`.
+    fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.status_code())
+    }
+
+    /// Whether the status code is `5xx`.
+    fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.status_code())
+    }
+
+    /// Return an [`crate::Error::Status`] if the status code is `4xx` or
+    /// `5xx`, or the response itself otherwise so the body can be streamed.
+    ///
+    /// The body isn't read, use [`ResponseBody::error_for_status`] after
+    /// [`CommonResponse::recv`] to get it in the error.
+    fn error_for_status(self) -> crate::DynResult<Self> {
+        let code = self.status_code();
+        if code >= 400 {
+            Err(crate::Error::Status { code, body: None }.into())
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Split the response into the status code, the headers and itself as a
+    /// reader of the body, so the headers stay around while streaming the
+    /// body.
+    ///
+    /// ```no_run
+    /// use alhc::prelude::*;
+    /// use alhc::*;
+    ///
+    /// # async fn run() -> DynResult {
+    /// let client = get_client_builder().build()?;
+    /// let res = client.get("https://httpbin.org/bytes/1024")?.await?;
+    /// let (status_code, headers, mut body) = res.split();
+    /// println!("{status_code} {headers:?}");
+    /// body.copy_to(&mut futures_lite::io::sink()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn split(self) -> (u16, HashMap<String, String>, Self) {
+        (self.status_code(), self.headers().clone(), self)
+    }
+
     /// Receive all data in memory and return a [`ResponseBody`]
     ///
     /// You can get binary data, status code or headers in it.
     async fn recv(self) -> std::io::Result<ResponseBody>;
 
-    /// Convenient method to receive data as string.
+    /// Read the rest of the body to the end, discarding it, and return the
+    /// trailers sent after it, which is empty if the server sent none. Their
+    /// names are lowercased.
+    ///
+    /// To keep the body as well, use [`ResponseBody::trailers`] after
+    /// [`CommonResponse::recv`].
+    async fn trailers(self) -> std::io::Result<HashMap<String, String>> {
+        Ok(self.recv().await?.trailers)
+    }
+
+    /// Convenient method to receive data as string, decoded like
+    /// [`ResponseBody::data_string`].
     async fn recv_string(self) -> std::io::Result<String> {
         Ok(self.recv().await?.data_string().into_owned())
     }
 
+    /// Receive data as string, failing with
+    /// [`std::io::ErrorKind::InvalidData`] if the body isn't valid UTF-8
+    /// instead of replacing the invalid characters.
+    async fn recv_string_strict(self) -> std::io::Result<String> {
+        String::from_utf8(self.recv_bytes().await?)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
     /// Convenient method to receive data as binary data.
     async fn recv_bytes(self) -> std::io::Result<Vec<u8>> {
         Ok(self.recv().await?.data)
     }
+
+    /// Receive data as binary data, failing with
+    /// [`std::io::ErrorKind::InvalidData`] once the body is larger than `max`
+    /// bytes, so no more than `max` bytes are ever buffered.
+    ///
+    /// Useful to receive the body of an untrusted server, a `Content-Length`
+    /// larger than `max` is rejected before anything is read.
+    async fn recv_bytes_with_limit(mut self, max: usize) -> std::io::Result<Vec<u8>> {
+        let limit = Some(max as u64);
+        let expected = self.content_length();
+        crate::response::check_body_limit(0, expected, limit)?;
+        let mut data = Vec::with_capacity(crate::response::body_capacity(expected, 8192).min(max));
+        let mut buf = [0; 8192];
+        loop {
+            let size = self.read(&mut buf).await?;
+            if size == 0 {
+                return Ok(data);
+            }
+            crate::response::check_body_limit((data.len() + size) as u64, None, limit)?;
+            if data.capacity() - data.len() < size {
+                // Grow like `Vec` does, but never past the limit
+                let capacity = (data.capacity() * 2).max(data.len() + size).min(max);
+                data.reserve_exact(capacity - data.len());
+            }
+            data.extend_from_slice(&buf[..size]);
+        }
+    }
+
+    /// An alias of [`CommonResponse::recv_string`].
+    ///
+    /// ```no_run
+    /// use alhc::prelude::*;
+    /// use alhc::*;
+    ///
+    /// # async fn run() -> DynResult {
+    /// let client = get_client_builder().build()?;
+    /// let text = client.get("https://httpbin.org/get")?.await?.text().await?;
+    /// println!("{text}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    async fn text(self) -> std::io::Result<String> {
+        self.recv_string().await
+    }
+
+    /// An alias of [`CommonResponse::recv_bytes`].
+    async fn bytes(self) -> std::io::Result<Vec<u8>> {
+        self.recv_bytes().await
+    }
+
+    /// Receive the next piece of the body as [`bytes::Bytes`], `None` at the
+    /// end of the body.
+    #[cfg(feature = "bytes")]
+    async fn chunk(&mut self) -> std::io::Result<Option<bytes::Bytes>> {
+        let mut buf = bytes::BytesMut::zeroed(8 * 1024);
+        let size = self.read(&mut buf).await?;
+        if size == 0 {
+            return Ok(None);
+        }
+        buf.truncate(size);
+        Ok(Some(buf.freeze()))
+    }
+
+    /// Turn the response into an [`AsyncBufRead`] to read the body line by
+    /// line with [`futures_lite::AsyncBufReadExt::lines`] or `read_until`.
+    ///
+    /// The response on Windows reads from the buffer filled by WinHTTP
+    /// directly, the others are wrapped in a [`futures_lite::io::BufReader`].
+    fn buffered(self) -> impl AsyncBufRead + Unpin {
+        futures_lite::io::BufReader::new(self)
+    }
+
+    /// Turn the response into a [`futures_lite::Stream`] of body chunks, to
+    /// feed them into stream based consumers.
+    fn bytes_stream(self) -> crate::BytesStream<Self> {
+        crate::BytesStream::new(self)
+    }
+
+    /// Stream the data into `writer` and return the number of bytes copied.
+    ///
+    /// Every chunk is fully written before the next one is read, so a slow
+    /// writer will slow down the transfer instead of letting data pile up in
+    /// memory.
+    async fn copy_to<W: AsyncWrite + Unpin>(mut self, writer: &mut W) -> std::io::Result<u64> {
+        let mut buf = vec![0; 8 * 1024];
+        let mut total = 0;
+        loop {
+            let size = self.read(&mut buf).await?;
+            if size == 0 {
+                break;
+            }
+            writer.write_all(&buf[..size]).await?;
+            total += size as u64;
+        }
+        writer.flush().await?;
+        Ok(total)
+    }
+
+    /// Stream the data into a file created at `path`, see
+    /// [`CommonResponse::copy_to`].
+    #[cfg(feature = "fs")]
+    async fn save(self, path: impl AsRef<Path>) -> std::io::Result<u64> {
+        let mut file = async_fs::File::create(path).await?;
+        let size = self.copy_to(&mut file).await?;
+        file.sync_all().await?;
+        Ok(size)
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -98,8 +563,30 @@ where
 pub trait CommonResponseSerdeExt: CommonResponse {
     /// Convenient method to receive data as a json data and deserialize data
     /// into a struct.
-    async fn recv_json<T: ?Sized + serde::de::DeserializeOwned>(self) -> crate::DynResult<T> {
-        Ok(serde_json::from_str(&self.recv_string().await?)?)
+    ///
+    /// The body is deserialized from the raw bytes, so it won't be copied
+    /// into an intermediate string first.
+    ///
+    /// ```no_run
+    /// use alhc::prelude::*;
+    /// use alhc::*;
+    ///
+    /// # async fn run() -> DynResult {
+    /// let client = get_client_builder().build()?;
+    /// let json: serde_json::Value = client.get("https://httpbin.org/json")?.await?.recv_json().await?;
+    /// println!("{}", json["slideshow"]["title"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    async fn recv_json<T: serde::de::DeserializeOwned>(self) -> crate::DynResult<T> {
+        self.recv().await?.data_json()
+    }
+
+    /// Parse the body as [JSON Lines](https://jsonlines.org) (or NDJSON)
+    /// incrementally while it arrives, into a [`futures_lite::Stream`] of
+    /// one value per line.
+    fn json_lines<T: serde::de::DeserializeOwned>(self) -> crate::JsonLines<Self, T> {
+        crate::JsonLines::new(self)
     }
 }
 
@@ -112,11 +599,58 @@ pub trait CommonClient {
     type ClientRequest: CommonRequest;
     /// Invoke a request with a method and a url, will return a
     /// [`CommonRequest`] implementation.
+    ///
+    /// A malformed URL, like one missing the `http` or `https` scheme or the
+    /// host, is rejected with [`crate::Error::InvalidUrl`] before any
+    /// connection is made.
+    ///
+    /// Percent-encoded credentials in the URL like `user:pass@host` are used
+    /// to authenticate with the server.
     fn request(&self, method: Method, url: &str) -> crate::DynResult<Self::ClientRequest>;
     /// Set connection timeout for new client.
     ///
-    /// Maybe no effect due to the implementation on platform.
+    /// Maybe no effect due to the implementation on platform. Prefer
+    /// [`crate::ClientBuilder::timeout`] to set it when building the client.
     fn set_timeout(&mut self, _max_timeout: Duration) {}
+    /// Build a request from an [`http::Request`], with its method, uri,
+    /// headers and body.
+    ///
+    /// The body is sent with the size in the `Content-Length` header, or
+    /// chunked if the header is absent. Set `Content-Length: 0` to send a
+    /// request without a body.
+    #[cfg(feature = "http")]
+    fn request_from_http<B>(&self, req: http::Request<B>) -> crate::DynResult<Self::ClientRequest>
+    where
+        B: AsyncRead + Unpin + Send + Sync + 'static,
+    {
+        let invalid_input =
+            |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg.to_owned());
+        let (parts, body) = req.into_parts();
+        let method = Method::from_name(parts.method.as_str())
+            .ok_or_else(|| invalid_input("unsupported request method"))?;
+        let mut req = self.request(method, &parts.uri.to_string())?;
+        let mut body_size = None;
+        for (name, value) in parts.headers.iter() {
+            let value = value
+                .to_str()
+                .map_err(|_| invalid_input("header value is not valid utf-8"))?;
+            if name == http::header::CONTENT_LENGTH {
+                body_size = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| invalid_input("invalid Content-Length header"))?,
+                );
+            } else if name != http::header::TRANSFER_ENCODING {
+                // Transfer encoding is decided by how the body is provided
+                req = req.header(name.as_str(), value);
+            }
+        }
+        Ok(match body_size {
+            Some(size) => req.body(body, size),
+            None => req.body_chunked(body),
+        })
+    }
 }
 
 /// Some convenient methods about [`CommonClient`].
@@ -155,6 +689,47 @@ pub trait CommonClientExt: CommonClient {
     fn options(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
         self.request(Method::OPTIONS, url)
     }
+
+    /// A wrapper of `CommonClient::request(Method::PROPFIND, url)`
+    fn propfind(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::PROPFIND, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::PROPPATCH, url)`
+    fn proppatch(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::PROPPATCH, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::MKCOL, url)`
+    fn mkcol(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::MKCOL, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::COPY, url)`
+    fn copy(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::COPY, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::MOVE, url)`, named with a
+    /// trailing underscore as `move` is a keyword.
+    fn move_(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::MOVE, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::LOCK, url)`
+    fn lock(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::LOCK, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::UNLOCK, url)`
+    fn unlock(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::UNLOCK, url)
+    }
+
+    /// A wrapper of `CommonClient::request(Method::REPORT, url)`
+    fn report(&self, url: &str) -> crate::DynResult<Self::ClientRequest> {
+        self.request(Method::REPORT, url)
+    }
 }
 
 impl<C: CommonClient> CommonClientExt for C {}
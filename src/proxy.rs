@@ -0,0 +1,121 @@
+/// The proxy configured by the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+/// environment variables, used by [`crate::ClientBuilder::proxy_from_env`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct EnvProxy {
+    pub(crate) http: Option<String>,
+    pub(crate) https: Option<String>,
+    /// Hosts that are connected to directly, a leading `.` matches all the
+    /// subdomains and `*` matches all the hosts.
+    pub(crate) no_proxy: Vec<String>,
+}
+
+impl EnvProxy {
+    /// Read the proxy from the environment, `None` if no proxy is set.
+    pub(crate) fn read() -> Option<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Read the proxy from the variables given by `lookup`, each by its
+    /// uppercase or lowercase name.
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let var = |name: &str| {
+            lookup(name)
+                .or_else(|| lookup(&name.to_ascii_lowercase()))
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+        };
+        let proxy = Self {
+            http: var("HTTP_PROXY"),
+            https: var("HTTPS_PROXY"),
+            no_proxy: var("NO_PROXY")
+                .map(|x| {
+                    x.split(',')
+                        .map(str::trim)
+                        .filter(|x| !x.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        if proxy.http.is_none() && proxy.https.is_none() {
+            None
+        } else {
+            Some(proxy)
+        }
+    }
+
+    /// The proxy to use for the URL, based on its scheme.
+    #[cfg(unix)]
+    pub(crate) fn for_url(&self, url: &str) -> Option<&str> {
+        let https = url
+            .get(..6)
+            .map(|x| x.eq_ignore_ascii_case("https:"))
+            .unwrap_or(false);
+        if https {
+            self.https.as_deref()
+        } else {
+            self.http.as_deref()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Option<EnvProxy> {
+        EnvProxy::from_vars(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn read_variables() {
+        assert_eq!(from_vars(&[]), None);
+        assert_eq!(from_vars(&[("NO_PROXY", "*")]), None);
+        assert_eq!(from_vars(&[("HTTP_PROXY", "  ")]), None);
+
+        let proxy = from_vars(&[
+            ("HTTP_PROXY", " http://proxy:3128 "),
+            ("https_proxy", "http://secure-proxy:3128"),
+            ("no_proxy", "localhost, .internal.example,,127.0.0.1 "),
+        ])
+        .unwrap();
+        assert_eq!(proxy.http.as_deref(), Some("http://proxy:3128"));
+        assert_eq!(proxy.https.as_deref(), Some("http://secure-proxy:3128"));
+        assert_eq!(
+            proxy.no_proxy,
+            ["localhost", ".internal.example", "127.0.0.1"]
+        );
+
+        // The uppercase name wins
+        let proxy = from_vars(&[
+            ("https_proxy", "http://lower:3128"),
+            ("HTTPS_PROXY", "http://upper:3128"),
+        ])
+        .unwrap();
+        assert_eq!(proxy.http, None);
+        assert_eq!(proxy.https.as_deref(), Some("http://upper:3128"));
+        assert!(proxy.no_proxy.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn proxy_for_url() {
+        let proxy = from_vars(&[("HTTP_PROXY", "http://proxy:3128")]).unwrap();
+        assert_eq!(
+            proxy.for_url("http://example.com/"),
+            Some("http://proxy:3128")
+        );
+        assert_eq!(proxy.for_url("HTTPS://example.com/"), None);
+
+        let proxy = from_vars(&[("HTTPS_PROXY", "http://secure-proxy:3128")]).unwrap();
+        assert_eq!(proxy.for_url("http://example.com/"), None);
+        assert_eq!(
+            proxy.for_url("HTTPS://example.com/"),
+            Some("http://secure-proxy:3128")
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::timer::wake_at;
+
+/// Spaces out the requests of a client evenly, so it never exceeds the
+/// configured rate.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / requests_per_second,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Take the next slot if it's due, or return when it will be.
+    fn try_acquire(&self) -> Result<(), Instant> {
+        let now = Instant::now();
+        let mut next_slot = self.next_slot.lock().unwrap();
+        if *next_slot <= now {
+            *next_slot = now + self.interval;
+            Ok(())
+        } else {
+            Err(*next_slot)
+        }
+    }
+
+    /// Hold back all the following requests for `delay`, used when the server
+    /// asks for it with `Retry-After`.
+    pub(crate) fn defer(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut next_slot = self.next_slot.lock().unwrap();
+        *next_slot = (*next_slot).max(until);
+    }
+
+    /// Defer the following requests if the response is a `429 Too Many
+    /// Requests` or `503 Service Unavailable` with a `Retry-After` in seconds.
+    pub(crate) fn handle_response(&self, status_code: u16, retry_after: Option<&str>) {
+        if status_code == 429 || status_code == 503 {
+            if let Some(secs) = retry_after.and_then(|x| x.trim().parse::<u64>().ok()) {
+                self.defer(Duration::from_secs(secs));
+            }
+        }
+    }
+}
+
+/// The waiting state of a request for its rate limit slot.
+#[derive(Debug)]
+pub(crate) struct RateLimitWait {
+    limiter: Arc<RateLimiter>,
+    armed_until: Option<Instant>,
+}
+
+impl RateLimitWait {
+    pub(crate) fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            limiter,
+            armed_until: None,
+        }
+    }
+
+    pub(crate) fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub(crate) fn poll_acquire(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match self.limiter.try_acquire() {
+            Ok(()) => Poll::Ready(()),
+            Err(until) => {
+                // Only one timer per slot is needed
+                if self.armed_until != Some(until) {
+                    self.armed_until = Some(until);
+                    wake_at(until, cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spaces_out_requests() {
+        let limiter = Arc::new(RateLimiter::new(20));
+        let start = Instant::now();
+        for _ in 0..3 {
+            let mut wait = RateLimitWait::new(limiter.clone());
+            futures_lite::future::block_on(futures_lite::future::poll_fn(|cx| {
+                wait.poll_acquire(cx)
+            }));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn defers_on_retry_after() {
+        let limiter = RateLimiter::new(1000);
+        limiter.handle_response(200, Some("5"));
+        assert!(limiter.try_acquire().is_ok());
+        limiter.handle_response(429, Some("5"));
+        let until = limiter.try_acquire().unwrap_err();
+        assert!(until >= Instant::now() + Duration::from_secs(4));
+    }
+}
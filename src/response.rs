@@ -1,11 +1,232 @@
 use std::collections::HashMap;
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// Look up a header case-insensitively, the first one is found if it appears
+/// multiple times.
+pub(crate) fn find_header<'a>(
+    headers: impl IntoIterator<Item = (&'a String, &'a String)>,
+    header: &str,
+) -> Option<&'a str> {
+    headers
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(header))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Add a received header to the map of the response headers. The values of a
+/// repeated header are joined with `, `, which means the same as receiving
+/// them one by one, except for `Set-Cookie` whose values can't be joined, so
+/// only the first one is kept.
+pub(crate) fn insert_header(headers: &mut HashMap<String, String>, name: String, value: &str) {
+    match headers.get_mut(&name) {
+        Some(_) if name.eq_ignore_ascii_case("Set-Cookie") => {}
+        Some(exist) => {
+            exist.push_str(", ");
+            exist.push_str(value);
+        }
+        None => {
+            headers.insert(name, value.to_owned());
+        }
+    }
+}
+
+/// All the values of a header joined with `, ` in the order they were added,
+/// which means the same as sending them one by one.
+pub(crate) fn join_header_values(headers: &[(String, String)], header: &str) -> Option<String> {
+    let mut values = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case(header))
+        .map(|(_, value)| value.as_str())
+        .peekable();
+    values.peek()?;
+    Some(values.collect::<Vec<_>>().join(", "))
+}
+
+pub(crate) fn content_length<'a, I>(headers: I) -> Option<u64>
+where
+    I: IntoIterator<Item = (&'a String, &'a String)> + Clone,
+{
+    let chunked = find_header(headers.clone(), "Transfer-Encoding")
+        .map(|x| x.to_ascii_lowercase().contains("chunked"))
+        .unwrap_or(false);
+    if chunked {
+        return None;
+    }
+    find_header(headers, "Content-Length").and_then(|x| x.trim().parse().ok())
+}
+
+/// The size the body should have according to `Content-Length`, `None` if
+/// it's unknown or the body is absent by definition, like `304 Not Modified`.
+///
+/// Responses to `HEAD` requests must be excluded by the caller.
+pub(crate) fn expected_body_size(code: u16, headers: &HashMap<String, String>) -> Option<u64> {
+    if (100..200).contains(&code) || code == 204 || code == 304 {
+        return None;
+    }
+    // A decoded body won't match the size on the wire
+    let encoded = find_header(headers, "Content-Encoding")
+        .map(|x| !x.trim().eq_ignore_ascii_case("identity"))
+        .unwrap_or(false);
+    if encoded {
+        return None;
+    }
+    content_length(headers)
+}
+
+/// The most memory reserved up front for receiving a body, so a huge
+/// `Content-Length` sent by a malicious server can't make us allocate it all.
+const MAX_PREALLOCATED_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The initial capacity of the buffer receiving a body of `expected_size`,
+/// or `fallback` if the size is unknown.
+pub(crate) fn body_capacity(expected_size: Option<u64>, fallback: usize) -> usize {
+    match expected_size {
+        Some(size) => size.min(MAX_PREALLOCATED_BODY_SIZE) as usize,
+        None => fallback,
+    }
+}
+
+/// The error returned when the connection is closed before the whole body has
+/// been received.
+pub(crate) fn truncated_body_error(received: u64, expected: u64) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        format!(
+            "the body is truncated, received {} of {} bytes",
+            received, expected
+        ),
+    )
+}
+
+/// Fail once the `received` bytes of a body, or the size it's expected to
+/// have, exceed the `max` size allowed by the client.
+pub(crate) fn check_body_limit(
+    received: u64,
+    expected: Option<u64>,
+    max: Option<u64>,
+) -> std::io::Result<()> {
+    match max {
+        Some(max) if received > max || expected.map(|x| x > max).unwrap_or(false) => {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("the body is larger than the limit of {} bytes", max),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+const ERROR_SNIPPET_LEN: usize = 256;
+
+/// The beginning of a body to be shown in errors.
+fn error_snippet(data: &[u8]) -> String {
+    let body = String::from_utf8_lossy(data);
+    match body.char_indices().nth(ERROR_SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.into_owned(),
+    }
+}
+
+/// HTTP versions a response can be received over, see
+/// [`crate::prelude::CommonResponse::http_version`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+    Http2,
+    Http3,
+}
+
+impl HttpVersion {
+    /// Parse the version of a status line, the part after `HTTP/` like `1.1`.
+    #[cfg_attr(all(unix, feature = "isahc"), allow(dead_code))]
+    pub(crate) fn parse(version: &str) -> Option<Self> {
+        match version {
+            "1.0" => Some(Self::Http10),
+            "1.1" => Some(Self::Http11),
+            "2" | "2.0" => Some(Self::Http2),
+            "3" | "3.0" => Some(Self::Http3),
+            _ => None,
+        }
+    }
+}
+
+/// How the connection of a response was encrypted, see
+/// [`crate::prelude::CommonResponse::tls_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    pub(crate) version: crate::TlsVersion,
+    pub(crate) cipher: String,
+}
+
+impl TlsInfo {
+    /// The version of TLS negotiated for the connection.
+    pub fn version(&self) -> crate::TlsVersion {
+        self.version
+    }
+
+    /// The name of the cipher suite negotiated for the connection, as named by
+    /// the TLS library of the system, like `TLS_AES_256_GCM_SHA384`.
+    ///
+    /// Names of suites before TLS 1.3 differ between libraries, OpenSSL names
+    /// them like `ECDHE-RSA-AES128-GCM-SHA256` while Windows uses the IANA
+    /// names like `TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256`.
+    pub fn cipher(&self) -> &str {
+        &self.cipher
+    }
+}
+
+/// How long a request took, measured from when it was last sent, so waiting
+/// for the limits of the client and for retries isn't counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub(crate) time_to_first_byte: Duration,
+    pub(crate) total: Option<Duration>,
+}
+
+impl Timing {
+    /// Start the timing of a response whose head has just been received.
+    pub(crate) fn new(sent_at: Instant) -> Self {
+        Self {
+            time_to_first_byte: sent_at.elapsed(),
+            total: None,
+        }
+    }
+
+    /// Record the end of the body, only the first call counts.
+    pub(crate) fn finish(&mut self, sent_at: Instant) {
+        self.total.get_or_insert_with(|| sent_at.elapsed());
+    }
+
+    /// The time until the head of the response was received.
+    pub fn time_to_first_byte(&self) -> Duration {
+        self.time_to_first_byte
+    }
+
+    /// The time until the whole body was received, `None` if the body hasn't
+    /// been read to the end yet.
+    pub fn total(&self) -> Option<Duration> {
+        self.total
+    }
+}
 
 pub struct ResponseBody {
     pub(crate) data: Vec<u8>,
     pub(crate) code: u16,
-    pub(crate) headers: HashMap<String, String>,
+    pub(crate) reason: Option<Cow<'static, str>>,
+    /// The headers in the order they were received.
+    pub(crate) headers: Vec<(String, String)>,
+    /// The headers in the order they were received, with their values as
+    /// the bytes sent by the server.
+    pub(crate) raw_headers: Vec<(String, Vec<u8>)>,
+    pub(crate) trailers: HashMap<String, String>,
+    pub(crate) timing: Timing,
 }
 
 impl ResponseBody {
@@ -17,23 +238,833 @@ impl ResponseBody {
         &self.data
     }
 
-    pub fn data_string(&self) -> Cow<str> {
+    /// Whether the body has no data, like the responses of `HEAD` requests
+    /// or `204 No Content`.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The body as text, invalid characters are replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER`.
+    ///
+    /// With the `charset` feature, the body is decoded with the charset in
+    /// the `Content-Type` header, or as UTF-8 if it's absent or unknown.
+    pub fn data_string(&self) -> Cow<'_, str> {
+        #[cfg(feature = "charset")]
+        if let Some(encoding) = self.content_type().and_then(|x| {
+            x.charset()
+                .and_then(|x| encoding_rs::Encoding::for_label(x.as_bytes()))
+        }) {
+            return encoding.decode(&self.data).0;
+        }
         String::from_utf8_lossy(&self.data)
     }
 
     #[cfg(feature = "serde")]
-    pub fn data_json<T: ?Sized + serde::de::DeserializeOwned>(self) -> crate::DynResult<T> {
+    pub fn data_json<T: serde::de::DeserializeOwned>(self) -> crate::DynResult<T> {
         Ok(serde_json::from_slice(&self.data)?)
     }
 
+    /// Like [`ResponseBody::data_json`], but on failure the error also carries
+    /// the status code and a snippet of the body, which makes schema
+    /// mismatches much easier to debug.
+    #[cfg(feature = "serde")]
+    pub fn json_into<T: serde::de::DeserializeOwned>(self) -> Result<T, JsonError> {
+        serde_json::from_slice(&self.data).map_err(|source| JsonError {
+            status_code: self.code,
+            snippet: error_snippet(&self.data),
+            source,
+        })
+    }
+
     pub fn status_code(&self) -> u16 {
         self.code
     }
 
+    /// Whether the status code is `2xx`.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code)
+    }
+
+    /// Whether the status code is `304 Not Modified`, which means the cached
+    /// copy of a conditional request is still valid.
+    pub fn not_modified(&self) -> bool {
+        self.code == 304
+    }
+
+    /// Whether the status code is `4xx`.
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code)
+    }
+
+    /// Whether the status code is `5xx`.
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code)
+    }
+
+    /// Return an [`crate::Error::Status`] carrying the status code and the
+    /// beginning of the body if the status code is `4xx` or `5xx`, or the
+    /// response itself otherwise.
+    pub fn error_for_status(self) -> crate::DynResult<Self> {
+        if self.code >= 400 {
+            Err(crate::Error::Status {
+                code: self.code,
+                body: Some(error_snippet(&self.data)),
+            }
+            .into())
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// The reason phrase of the status line, like `Not Found`.
+    ///
+    /// It's the phrase sent by the server, which may be missing over HTTP/2.
+    /// With the `isahc` feature on Unix it's the canonical phrase of the
+    /// status code instead, as the one sent by the server isn't exposed by
+    /// `isahc`.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Look up a header case-insensitively, the first one is returned if it
+    /// appears multiple times.
+    ///
+    /// Older versions returned the values of a repeated header like
+    /// `Set-Cookie` joined with `; ` on Windows, use
+    /// [`ResponseBody::headers_iter`] to get all of them now.
     pub fn header(&self, header: &str) -> Option<&str> {
+        find_header(
+            self.headers.iter().map(|(name, value)| (name, value)),
+            header,
+        )
+    }
+
+    /// Get the value of a header as the bytes sent by the server, unlike
+    /// [`ResponseBody::header`] which decodes it to a string and loses the
+    /// bytes that aren't valid, like Latin-1 values.
+    ///
+    /// If the header is repeated, the first one is returned.
+    pub fn raw_header_bytes(&self, header: &str) -> Option<&[u8]> {
+        self.raw_headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Iterate over the headers in the order they were received, including
+    /// every occurrence of the repeated ones.
+    pub fn headers_iter(&self) -> impl Iterator<Item = (&str, &str)> {
         self.headers
-            .keys()
-            .find(|x| x.eq_ignore_ascii_case(header))
-            .and_then(|x| self.headers.get(x).map(String::as_str))
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// The trailers sent after the body, like `grpc-status` of gRPC-web,
+    /// which is empty if the server sent none. Their names are lowercased.
+    pub fn trailers(&self) -> &HashMap<String, String> {
+        &self.trailers
+    }
+
+    /// How long the request and receiving the whole body took.
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// The size of the body in the `Content-Length` header, `None` if it's
+    /// absent or the body is chunked.
+    pub fn content_length(&self) -> Option<u64> {
+        content_length(self.headers.iter().map(|(name, value)| (name, value)))
+    }
+
+    /// Look up a header and parse it into the requested type.
+    ///
+    /// Returns `None` if the header is absent or can't be parsed.
+    pub fn header_parse<T: FromStr>(&self, header: &str) -> Option<T> {
+        self.header(header).and_then(|x| x.trim().parse().ok())
+    }
+
+    /// Parse the `Content-Type` header, to tell how the body should be
+    /// decoded, like as text or as JSON.
+    ///
+    /// Returns `None` if the header is absent or isn't a valid media type.
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("Content-Type").and_then(ContentType::parse)
+    }
+
+    /// The file name suggested by the `filename` parameter of the
+    /// `Content-Disposition` header, like `report.pdf` of
+    /// `attachment; filename="report.pdf"`.
+    ///
+    /// The encoded `filename*` parameter is preferred if it's present, like
+    /// `filename*=UTF-8''%E2%82%AC%20rates.txt`. Any directory in the name is
+    /// dropped, so it can't point outside of where it's saved.
+    pub fn filename(&self) -> Option<String> {
+        let params = split_unquoted(self.header("Content-Disposition")?, ';')
+            .skip(1)
+            .filter_map(|param| param.split_once('='));
+        let mut plain = None;
+        let mut encoded = None;
+        for (name, value) in params {
+            match name.trim() {
+                name if name.eq_ignore_ascii_case("filename*") => {
+                    encoded = encoded.or_else(|| decode_ext_value(value));
+                }
+                name if name.eq_ignore_ascii_case("filename") => {
+                    plain = plain.or_else(|| Some(unquote(value)));
+                }
+                _ => {}
+            }
+        }
+        let name = encoded.or(plain)?;
+        let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+        if name.is_empty() || name == "." || name == ".." {
+            return None;
+        }
+        Some(name.to_owned())
+    }
+
+    /// Parse the `Content-Range` header of a `206 Partial Content` or `416
+    /// Range Not Satisfiable` response, see [`crate::prelude::CommonRequest::range`].
+    ///
+    /// Returns `None` if the header is absent or isn't a valid byte range.
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self.header("Content-Range").and_then(ContentRange::parse)
+    }
+
+    /// Parse the `Allow` header of an `OPTIONS` or `405 Method Not Allowed`
+    /// response into the methods the resource supports, like `GET, POST,
+    /// OPTIONS`. Methods ALHC doesn't know are skipped.
+    pub fn allowed_methods(&self) -> Vec<crate::Method> {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Allow"))
+            .flat_map(|(_, value)| value.split(','))
+            .filter_map(|x| x.trim().parse().ok())
+            .collect()
+    }
+
+    /// The `Access-Control-Allow-Origin` header of a CORS response, which is
+    /// either `*` or the origin allowed to read the response.
+    pub fn access_control_allow_origin(&self) -> Option<&str> {
+        self.header("Access-Control-Allow-Origin")
+    }
+
+    /// Parse the `Link` header into a map from the relation type (`next`,
+    /// `prev`, `last`...) to the linked URL, which is often used by
+    /// paginated APIs.
+    ///
+    /// Relation types are lowercased, and a link with multiple relation types
+    /// will be inserted for each of them. The links of all the `Link` headers
+    /// are included if the header is repeated.
+    pub fn links(&self) -> HashMap<String, String> {
+        let mut links = HashMap::new();
+        let values = self
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Link"));
+        for (_, value) in values {
+            let mut rest = value.as_str();
+            while let Some(start) = rest.find('<') {
+                let Some(end) = rest[start..].find('>') else {
+                    break;
+                };
+                let url = &rest[start + 1..start + end];
+                rest = &rest[start + end + 1..];
+                let params = &rest[..rest.find('<').unwrap_or(rest.len())];
+                let rel = params
+                    .split([';', ','])
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("rel"))
+                    .map(|(_, value)| value.trim().trim_matches('"'));
+                for rel in rel.unwrap_or_default().split_whitespace() {
+                    links.insert(rel.to_ascii_lowercase(), url.to_owned());
+                }
+            }
+        }
+        links
+    }
+}
+
+/// A media type parsed from a `Content-Type` header, like
+/// `text/html; charset=utf-8`, see [`ResponseBody::content_type`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    media_type: String,
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let mut parts = split_unquoted(value, ';');
+        let media_type = parts.next()?.trim().to_ascii_lowercase();
+        let (kind, subtype) = media_type.split_once('/')?;
+        if kind.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value)))
+            .collect();
+        Some(Self { media_type, params })
+    }
+
+    /// The lowercased media type without parameters, like `text/html`.
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// The top level type, like `text` of `text/html`.
+    pub fn type_(&self) -> &str {
+        self.media_type.split_once('/').unwrap_or_default().0
+    }
+
+    /// The subtype, like `html` of `text/html`.
+    pub fn subtype(&self) -> &str {
+        self.media_type.split_once('/').unwrap_or_default().1
+    }
+
+    /// Look up a parameter case-insensitively, like `boundary`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The `charset` parameter, like `utf-8`.
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+}
+
+/// A byte range parsed from a `Content-Range` header, like
+/// `bytes 0-499/1234`, see [`ResponseBody::content_range`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentRange {
+    range: Option<RangeInclusive<u64>>,
+    complete_length: Option<u64>,
+}
+
+impl ContentRange {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (unit, value) = value.trim().split_once(' ')?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let (range, complete_length) = value.trim().split_once('/')?;
+        let complete_length = match complete_length {
+            "*" => None,
+            length => Some(length.parse().ok()?),
+        };
+        let range = match range {
+            // An unsatisfied range only tells the complete length
+            "*" if complete_length.is_some() => None,
+            range => {
+                let (start, end) = range.split_once('-')?;
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                if start > end || complete_length.is_some_and(|x| end >= x) {
+                    return None;
+                }
+                Some(start..=end)
+            }
+        };
+        Some(Self {
+            range,
+            complete_length,
+        })
+    }
+
+    /// The first and the last byte position of the body in the whole
+    /// content, `None` for the `bytes */1234` of an unsatisfiable range.
+    pub fn range(&self) -> Option<RangeInclusive<u64>> {
+        self.range.clone()
+    }
+
+    /// The size of the whole content, `None` if the server doesn't know it.
+    pub fn complete_length(&self) -> Option<u64> {
+        self.complete_length
+    }
+}
+
+/// The value of a header parameter, without the double quotes and the
+/// backslashes escaping the characters if it's quoted.
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(quoted) => {
+            // Drop the backslashes escaping the next character
+            let mut unescaped = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                unescaped.push(match c {
+                    '\\' => chars.next().unwrap_or(c),
+                    c => c,
+                });
+            }
+            unescaped
+        }
+        None => value.to_owned(),
+    }
+}
+
+/// Decode an extended parameter value of RFC 8187 like
+/// `UTF-8''%E2%82%AC%20rates`, only UTF-8 and ISO-8859-1 are supported.
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.trim().splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes = percent_decode(encoded)?;
+    if charset.eq_ignore_ascii_case("UTF-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+/// Split a raw header line like `Name: value` into its name and the bytes of
+/// its value, keeping the value undecoded.
+#[cfg_attr(all(unix, feature = "isahc"), allow(dead_code))]
+pub(crate) fn split_raw_header(line: &[u8]) -> Option<(String, Vec<u8>)> {
+    let colon = line.iter().position(|&x| x == b':')?;
+    let name = String::from_utf8_lossy(line[..colon].trim_ascii());
+    Some((name.into_owned(), line[colon + 1..].trim_ascii().to_vec()))
+}
+
+/// Decode the `%XX` escapes of `value`, or `None` if one of them is invalid.
+pub(crate) fn percent_decode(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
+
+/// Split `value` on `separator` outside of double quoted strings.
+fn split_unquoted(value: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    let mut escaped = false;
+    value.split(move |c| {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else {
+            return !quoted && c == separator;
+        }
+        false
+    })
+}
+
+#[cfg(feature = "http")]
+impl From<ResponseBody> for http::Response<Vec<u8>> {
+    /// Headers that aren't valid for the `http` crate are skipped, and an
+    /// invalid status code becomes `502 Bad Gateway`.
+    fn from(body: ResponseBody) -> Self {
+        let mut res = http::Response::new(body.data);
+        *res.status_mut() =
+            http::StatusCode::from_u16(body.code).unwrap_or(http::StatusCode::BAD_GATEWAY);
+        let headers = res.headers_mut();
+        headers.reserve(body.headers.len());
+        for (name, value) in body.headers {
+            if let (Ok(name), Ok(value)) = (
+                http::header::HeaderName::from_bytes(name.as_bytes()),
+                http::header::HeaderValue::from_str(&value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        res
+    }
+}
+
+/// The error returned by [`ResponseBody::json_into`].
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct JsonError {
+    status_code: u16,
+    snippet: String,
+    source: serde_json::Error,
+}
+
+#[cfg(feature = "serde")]
+impl JsonError {
+    /// The status code of the response that failed to deserialize.
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    /// The beginning of the body that failed to deserialize.
+    pub fn snippet(&self) -> &str {
+        &self.snippet
+    }
+
+    /// The underlying `serde_json` error.
+    pub fn json_error(&self) -> &serde_json::Error {
+        &self.source
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to deserialize response with status {}: {} (body: {:?})",
+            self.status_code, self.source, self.snippet
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(headers: &[(&str, &str)]) -> ResponseBody {
+        ResponseBody {
+            data: Vec::new(),
+            code: 200,
+            reason: None,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            raw_headers: Vec::new(),
+            trailers: HashMap::new(),
+            timing: Timing::new(Instant::now()),
+        }
+    }
+
+    #[test]
+    fn links() {
+        let res = response(&[(
+            "Link",
+            "<https://api.github.com/repos?page=2>; rel=\"next\", \
+             <https://api.github.com/repos?page=5>; rel=\"last\"",
+        )]);
+        let links = res.links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links["next"], "https://api.github.com/repos?page=2");
+        assert_eq!(links["last"], "https://api.github.com/repos?page=5");
+
+        // Several relation types, other parameters and a URL with `;` and `,`
+        let res = response(&[(
+            "link",
+            "<https://example.com/a;b,c>; title=\"x\"; REL=\"Prev First\", <https://example.com/>",
+        )]);
+        let links = res.links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links["prev"], "https://example.com/a;b,c");
+        assert_eq!(links["first"], "https://example.com/a;b,c");
+
+        assert!(response(&[]).links().is_empty());
+        assert!(response(&[("Link", "<https://example.com/")])
+            .links()
+            .is_empty());
+    }
+
+    #[test]
+    fn links_of_repeated_headers() {
+        let res = response(&[
+            ("Link", "<https://api.example/?page=2>; rel=\"next\""),
+            ("Content-Type", "application/json"),
+            ("link", "<https://api.example/?page=9>; rel=last"),
+        ]);
+        let links = res.links();
+        assert_eq!(links.len(), 2);
+        assert_eq!(links["next"], "https://api.example/?page=2");
+        assert_eq!(links["last"], "https://api.example/?page=9");
+        assert_eq!(
+            res.header("LINK"),
+            Some("<https://api.example/?page=2>; rel=\"next\"")
+        );
+    }
+
+    #[test]
+    fn body_limit() {
+        assert!(check_body_limit(u64::MAX, Some(u64::MAX), None).is_ok());
+        assert!(check_body_limit(10, None, Some(10)).is_ok());
+        assert!(check_body_limit(5, Some(10), Some(10)).is_ok());
+
+        let err = check_body_limit(11, None, Some(10)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            err.to_string(),
+            "the body is larger than the limit of 10 bytes"
+        );
+        // Fails before receiving anything if the body is known to be too large
+        assert!(check_body_limit(0, Some(11), Some(10)).is_err());
+    }
+
+    #[test]
+    fn expected_size_of_empty_bodies() {
+        let headers = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let sized = headers(&[("Content-Length", "5")]);
+        assert_eq!(expected_body_size(200, &sized), Some(5));
+        for code in [101, 204, 304] {
+            assert_eq!(expected_body_size(code, &sized), None, "{code}");
+        }
+        assert_eq!(
+            expected_body_size(200, &headers(&[("content-length", " 0 ")])),
+            Some(0)
+        );
+        assert_eq!(expected_body_size(200, &headers(&[])), None);
+        assert_eq!(
+            expected_body_size(
+                200,
+                &headers(&[("Content-Length", "5"), ("Content-Encoding", "gzip")])
+            ),
+            None
+        );
+        assert_eq!(
+            expected_body_size(
+                200,
+                &headers(&[("Content-Length", "5"), ("Transfer-Encoding", "chunked")])
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn content_types() {
+        let ty = ContentType::parse("Text/HTML; Charset=\"utf-8\"; q=\"a;b\"").unwrap();
+        assert_eq!(ty.media_type(), "text/html");
+        assert_eq!(ty.type_(), "text");
+        assert_eq!(ty.subtype(), "html");
+        assert_eq!(ty.charset(), Some("utf-8"));
+        assert_eq!(ty.param("Q"), Some("a;b"));
+        assert_eq!(ty.param("boundary"), None);
+
+        let ty = ContentType::parse("multipart/form-data;boundary=\"a\\\"b\"").unwrap();
+        assert_eq!(ty.param("boundary"), Some("a\"b"));
+
+        for invalid in ["", "text", "text/", "/html", "; charset=utf-8"] {
+            assert_eq!(ContentType::parse(invalid), None, "{invalid}");
+        }
+        assert_eq!(
+            response(&[("content-type", "application/json")])
+                .content_type()
+                .unwrap()
+                .media_type(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn content_ranges() {
+        let range = ContentRange::parse("bytes 0-499/1234").unwrap();
+        assert_eq!(range.range(), Some(0..=499));
+        assert_eq!(range.complete_length(), Some(1234));
+
+        let range = ContentRange::parse(" BYTES 500-999/*").unwrap();
+        assert_eq!(range.range(), Some(500..=999));
+        assert_eq!(range.complete_length(), None);
+
+        // Unsatisfiable range
+        let range = ContentRange::parse("bytes */1234").unwrap();
+        assert_eq!(range.range(), None);
+        assert_eq!(range.complete_length(), Some(1234));
+
+        for invalid in [
+            "",
+            "bytes",
+            "items 0-1/2",
+            "bytes */*",
+            "bytes 5-4/10",
+            "bytes 0-10/10",
+            "bytes -1/10",
+            "bytes 0-1",
+            "bytes a-b/10",
+        ] {
+            assert_eq!(ContentRange::parse(invalid), None, "{invalid}");
+        }
+        assert_eq!(
+            response(&[("Content-Range", "bytes 0-0/1")])
+                .content_range()
+                .and_then(|x| x.range()),
+            Some(0..=0)
+        );
+    }
+
+    #[test]
+    fn filenames() {
+        let filename = |value| response(&[("Content-Disposition", value)]).filename();
+        assert_eq!(
+            filename("attachment; filename=\"report.pdf\""),
+            Some("report.pdf".into())
+        );
+        assert_eq!(
+            filename("attachment; FILENAME=report.pdf"),
+            Some("report.pdf".into())
+        );
+        assert_eq!(
+            filename("attachment; filename=\"a \\\"b\\\"; c.txt\""),
+            Some("a \"b\"; c.txt".into())
+        );
+        // The encoded name is preferred wherever it is
+        assert_eq!(
+            filename("attachment; filename*=UTF-8''%E2%82%AC%20rates.txt; filename=rates.txt"),
+            Some("€ rates.txt".into())
+        );
+        assert_eq!(
+            filename("attachment; filename=rates.txt; filename*=iso-8859-1'en'%A3%20rates.txt"),
+            Some("£ rates.txt".into())
+        );
+        // Falls back to the plain name if the encoded one can't be decoded
+        assert_eq!(
+            filename("attachment; filename*=UTF-16''%FF; filename=rates.txt"),
+            Some("rates.txt".into())
+        );
+        assert_eq!(
+            filename("attachment; filename=\"../../etc/passwd\""),
+            Some("passwd".into())
+        );
+        assert_eq!(
+            filename("attachment; filename=\"C:\\\\temp\\\\a.txt\""),
+            Some("a.txt".into())
+        );
+        for none in [
+            "attachment",
+            "inline; name=\"a.txt\"",
+            "attachment; filename=\"\"",
+            "attachment; filename=\"..\"",
+            "attachment; filename=\"dir/\"",
+        ] {
+            assert_eq!(filename(none), None, "{none}");
+        }
+        assert_eq!(response(&[]).filename(), None);
+    }
+
+    #[test]
+    fn raw_headers() {
+        assert_eq!(
+            split_raw_header(b"Content-Type: text/plain\r\n"),
+            Some(("Content-Type".into(), b"text/plain".to_vec()))
+        );
+        assert_eq!(
+            split_raw_header(b" X-Name :\t caf\xe9 \r\n"),
+            Some(("X-Name".into(), b"caf\xe9".to_vec()))
+        );
+        // Only the first colon splits
+        assert_eq!(
+            split_raw_header(b"Location: http://example.com:8080/"),
+            Some(("Location".into(), b"http://example.com:8080/".to_vec()))
+        );
+        assert_eq!(
+            split_raw_header(b"X-Empty:"),
+            Some(("X-Empty".into(), Vec::new()))
+        );
+        assert_eq!(split_raw_header(b"HTTP/1.1 200 OK\r\n"), None);
+        assert_eq!(split_raw_header(b"\r\n"), None);
+
+        let mut res = response(&[("X-Name", "caf\u{fffd}")]);
+        res.raw_headers = [&b"X-Name: caf\xe9"[..], b"X-Name: second"]
+            .into_iter()
+            .filter_map(split_raw_header)
+            .collect();
+        assert_eq!(res.raw_header_bytes("x-name"), Some(&b"caf\xe9"[..]));
+        assert_eq!(res.raw_header_bytes("X-Other"), None);
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let mut headers = HashMap::new();
+        for (name, value) in [
+            ("vary", "Accept"),
+            ("set-cookie", "a=1; Path=/"),
+            ("vary", "Origin"),
+            ("set-cookie", "b=2"),
+        ] {
+            insert_header(&mut headers, name.to_owned(), value);
+        }
+        assert_eq!(headers["vary"], "Accept, Origin");
+        assert_eq!(headers["set-cookie"], "a=1; Path=/");
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn latin1_bodies() {
+        let mut res = response(&[("Content-Type", "text/plain; charset=ISO-8859-1")]);
+        res.data = b"caf\xe9".to_vec();
+        assert_eq!(res.data_string(), "café");
+
+        // Unknown charsets fall back to UTF-8
+        let mut res = response(&[("Content-Type", "text/plain; charset=x-unknown")]);
+        res.data = "café".as_bytes().to_vec();
+        assert_eq!(res.data_string(), "café");
+    }
+
+    #[test]
+    fn parsed_headers() {
+        let res = response(&[("X-RateLimit-Remaining", " 42 "), ("X-Retry", "soon")]);
+        assert_eq!(res.header_parse::<u32>("x-ratelimit-remaining"), Some(42));
+        assert_eq!(res.header_parse::<u32>("X-Retry"), None);
+        assert_eq!(res.header_parse::<u32>("X-RateLimit-Reset"), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn mismatched_json() {
+        let mut res = response(&[("Content-Type", "application/json")]);
+        res.code = 422;
+        res.data = br#"{"error":"missing field"}"#.to_vec();
+        let Err(err) = res.json_into::<Vec<u32>>() else {
+            panic!("an object is not a list");
+        };
+        assert_eq!(err.status_code(), 422);
+        assert_eq!(err.snippet(), r#"{"error":"missing field"}"#);
+        let message = err.to_string();
+        assert!(message.contains("422"), "{message}");
+        assert!(
+            message.contains(r#"{\"error\":\"missing field\"}"#),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn cors_headers() {
+        let res = response(&[
+            ("Allow", "GET,  post ,OPTIONS, BREW"),
+            ("Access-Control-Allow-Origin", "https://example.com"),
+        ]);
+        assert_eq!(
+            res.allowed_methods(),
+            [
+                crate::Method::GET,
+                crate::Method::POST,
+                crate::Method::OPTIONS
+            ]
+        );
+        assert_eq!(
+            res.access_control_allow_origin(),
+            Some("https://example.com")
+        );
+
+        let res = response(&[]);
+        assert!(res.allowed_methods().is_empty());
+        assert_eq!(res.access_control_allow_origin(), None);
     }
 }
@@ -0,0 +1,263 @@
+use std::{
+    any::Any,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::ErrorKind,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_lite::io::Cursor;
+
+use crate::Method;
+
+/// The longest backoff between two attempts, unless the server asks for a
+/// longer one with `Retry-After`.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// A policy to send idempotent requests again on transient failures, set by
+/// [`crate::ClientBuilder::retry`].
+///
+/// Only `GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and `TRACE` requests are
+/// retried, and only when their body can be sent again, which means no body
+/// or one provided by [`crate::prelude::CommonRequest::body_bytes`],
+/// [`crate::prelude::CommonRequest::body_string`] or
+/// [`crate::prelude::CommonRequest::body_empty`]. Otherwise the failure is
+/// returned as is.
+///
+/// ```
+/// use std::time::Duration;
+/// use alhc::RetryPolicy;
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .base_delay(Duration::from_millis(500))
+///     .retry_statuses([429, 503]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    multiplier: f64,
+    jitter: bool,
+    statuses: Vec<u16>,
+    error_kinds: Vec<ErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            multiplier: 2.0,
+            jitter: true,
+            statuses: vec![408, 429, 500, 502, 503, 504],
+            error_kinds: vec![
+                ErrorKind::ConnectionRefused,
+                ErrorKind::ConnectionReset,
+                ErrorKind::ConnectionAborted,
+                ErrorKind::NotConnected,
+                ErrorKind::BrokenPipe,
+                ErrorKind::TimedOut,
+                ErrorKind::UnexpectedEof,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy making at most 3 attempts, waiting 200ms before the first
+    /// retry and doubling it each time with jitter. It retries on connection
+    /// failures, timeouts and the `408`, `429`, `500`, `502`, `503` and `504`
+    /// status codes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of attempts including the first one, so `1` disables
+    /// retrying.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// The delay before the first retry.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// How much the delay grows after each retry, the delay is capped at 30s.
+    pub fn backoff(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Randomly shorten each delay by up to a half, so clients failing
+    /// together don't retry together.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The status codes of the responses to retry on, replacing the default
+    /// ones.
+    pub fn retry_statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// The kinds of the errors to retry on, replacing the default ones.
+    pub fn retry_error_kinds(mut self, kinds: impl IntoIterator<Item = ErrorKind>) -> Self {
+        self.error_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    /// Whether another attempt can be made after `attempts` attempts.
+    pub(crate) fn has_attempts_left(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    pub(crate) fn should_retry_status(&self, status_code: u16) -> bool {
+        self.statuses.contains(&status_code)
+    }
+
+    pub(crate) fn should_retry_error(&self, kind: ErrorKind) -> bool {
+        self.error_kinds.contains(&kind)
+    }
+
+    /// The delay before the `attempt`th retry, at least as long as the
+    /// `Retry-After` in seconds sent by the server.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<&str>) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let factor = self.multiplier.powi(exponent);
+        // Capped in seconds first, as a `Duration` would overflow after many
+        // attempts
+        let secs = self.base_delay.as_secs_f64() * factor;
+        let mut delay = Duration::from_secs_f64(secs.min(MAX_DELAY.as_secs_f64()));
+        if self.jitter {
+            delay = delay.mul_f64(1.0 - random_unit() / 2.0);
+        }
+        match retry_after.and_then(|x| x.trim().parse().ok()) {
+            Some(secs) => delay.max(Duration::from_secs(secs)),
+            None => delay,
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for jitter without pulling in a
+/// random number generator.
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+/// Whether sending the request more than once has the same effect as sending
+/// it once.
+pub(crate) fn is_idempotent(method: Method) -> bool {
+    matches!(
+        method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// The data of a buffered body, which can be read again for every attempt.
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayBody(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for ReplayBody {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl ReplayBody {
+    /// Take the data out of a buffered body, or give the body back if it's a
+    /// stream that can only be read once.
+    pub(crate) fn from_body<B: 'static>(body: B) -> Result<Self, B> {
+        let body: Box<dyn Any> = Box::new(body);
+        let body = match body.downcast::<Cursor<Vec<u8>>>() {
+            Ok(cursor) if cursor.position() == 0 => return Ok(Self(Arc::new(cursor.into_inner()))),
+            Ok(cursor) => cursor as Box<dyn Any>,
+            Err(body) => body,
+        };
+        let body = match body.downcast::<futures_lite::io::Empty>() {
+            Ok(_) => return Ok(Self(Arc::default())),
+            Err(body) => body,
+        };
+        Err(*body
+            .downcast::<B>()
+            .unwrap_or_else(|_| unreachable!("the body has its own type")))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// A new reader from the start of the body.
+    pub(crate) fn reader(&self) -> Cursor<Self> {
+        Cursor::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_delays() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_millis(100))
+            .jitter(false);
+        assert_eq!(policy.delay(1, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(200));
+        assert_eq!(policy.delay(4, None), Duration::from_millis(800));
+        assert_eq!(policy.delay(20, None), MAX_DELAY);
+        assert_eq!(policy.delay(u32::MAX, None), MAX_DELAY);
+
+        let policy = policy.backoff(3.0);
+        assert_eq!(policy.delay(3, None), Duration::from_millis(900));
+        // A multiplier below 1 would shrink the delays
+        let policy = policy.backoff(0.5);
+        assert_eq!(policy.delay(3, None), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn jittered_delays() {
+        let policy = RetryPolicy::new().base_delay(Duration::from_secs(1));
+        for _ in 0..100 {
+            let delay = policy.delay(2, None);
+            assert!(delay > Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn retry_after() {
+        let policy = RetryPolicy::new()
+            .base_delay(Duration::from_secs(1))
+            .jitter(false);
+        assert_eq!(policy.delay(1, Some(" 5 ")), Duration::from_secs(5));
+        // Above the cap, as the server asks for it
+        assert_eq!(policy.delay(1, Some("120")), Duration::from_secs(120));
+        // Never shorter than the backoff
+        assert_eq!(policy.delay(3, Some("1")), Duration::from_secs(4));
+        // An HTTP date isn't supported
+        assert_eq!(
+            policy.delay(1, Some("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn attempts() {
+        let policy = RetryPolicy::new();
+        assert!(policy.has_attempts_left(2));
+        assert!(!policy.has_attempts_left(3));
+        assert!(!RetryPolicy::new().max_attempts(1).has_attempts_left(1));
+        assert!(policy.should_retry_status(503));
+        assert!(!policy.should_retry_status(404));
+        assert!(policy.should_retry_error(ErrorKind::TimedOut));
+        assert!(!policy.should_retry_error(ErrorKind::PermissionDenied));
+    }
+}
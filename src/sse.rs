@@ -0,0 +1,282 @@
+//! Parsing of [Server-Sent Events](https://html.spec.whatwg.org/multipage/server-sent-events.html)
+//! from a `text/event-stream` response.
+//!
+//! ```no_run
+//! use alhc::prelude::*;
+//! use alhc::sse::CommonResponseSseExt;
+//! use alhc::*;
+//! use futures_lite::StreamExt;
+//!
+//! # async fn run() -> DynResult {
+//! let client = get_client_builder().build()?;
+//! let mut events = client.get("https://example.com/events")?.await?.events();
+//! while let Some(event) = events.next().await {
+//!     println!("{}", event?.data);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_lite::{AsyncRead, Stream};
+
+use crate::prelude::CommonResponse;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// An event received from the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    /// The type of the event, `None` means the default `message` type.
+    pub event: Option<String>,
+    /// The data of the event, multiple `data` fields are joined by `\n`.
+    pub data: String,
+    /// The last event ID set by the server, which is kept across events.
+    pub id: Option<String>,
+    /// The reconnection time in milliseconds, if the event sets it.
+    pub retry: Option<u64>,
+}
+
+/// A [`Stream`] of [`Event`]s parsed from a response, created by
+/// [`CommonResponseSseExt::events`].
+#[derive(Debug)]
+pub struct EventStream<R> {
+    reader: R,
+    buf: Vec<u8>,
+    skip_lf: bool,
+    started: bool,
+    finished: bool,
+    event: Option<String>,
+    data: String,
+    has_data: bool,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl<R> EventStream<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            skip_lf: false,
+            started: false,
+            finished: false,
+            event: None,
+            data: String::new(),
+            has_data: false,
+            last_id: None,
+            retry: None,
+        }
+    }
+
+    /// Take a complete line from the buffer, lines may end with `\r\n`, `\n`
+    /// or `\r`.
+    fn next_line(&mut self) -> Option<String> {
+        if self.skip_lf && self.buf.first() == Some(&b'\n') {
+            self.buf.remove(0);
+        }
+        if !self.buf.is_empty() {
+            self.skip_lf = false;
+        }
+        let end = self.buf.iter().position(|x| *x == b'\r' || *x == b'\n')?;
+        self.skip_lf = self.buf[end] == b'\r';
+        let line: Vec<u8> = self.buf.drain(..=end).take(end).collect();
+        let mut line = String::from_utf8_lossy(&line).into_owned();
+        if !self.started {
+            self.started = true;
+            if let Some(stripped) = line.strip_prefix('\u{feff}') {
+                line = stripped.to_owned();
+            }
+        }
+        Some(line)
+    }
+
+    /// Process a line, and return the event if the line ends it.
+    fn process_line(&mut self, line: &str) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            // A comment
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_owned()),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_owned()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+        let retry = self.retry.take();
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        Some(Event {
+            event,
+            data: std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+            retry,
+        })
+    }
+
+    /// Get back the underlying response.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for EventStream<R> {
+    type Item = std::io::Result<Event>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            while let Some(line) = self.next_line() {
+                if let Some(event) = self.process_line(&line) {
+                    return Poll::Ready(Some(Ok(event)));
+                }
+            }
+            if self.finished {
+                return Poll::Ready(None);
+            }
+            let mut chunk = [0; CHUNK_SIZE];
+            match Pin::new(&mut self.reader).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => {
+                    // An incomplete event at the end of the stream is dropped
+                    self.finished = true;
+                    return Poll::Ready(None);
+                }
+                Poll::Ready(Ok(size)) => self.buf.extend_from_slice(&chunk[..size]),
+                Poll::Ready(Err(err)) => {
+                    self.finished = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A trait that allows you to receive a response as Server-Sent Events.
+pub trait CommonResponseSseExt: CommonResponse {
+    /// Parse the body as a `text/event-stream` incrementally while it
+    /// arrives.
+    fn events(self) -> EventStream<Self> {
+        EventStream::new(self)
+    }
+}
+
+impl<R: CommonResponse> CommonResponseSseExt for R {}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::StreamExt;
+
+    use super::*;
+
+    const STREAM: &str = "\u{feff}: comment\r\n\
+                          event: greet\r\ndata: hello\r\ndata:  world\r\nid: 1\r\nretry: 3000\r\n\r\n\
+                          data: second\n\n\
+                          event: ignored\n\n\
+                          data\rid\r\r\
+                          id: a\0b\ndata: x\n\n\
+                          retry: soon\ndata: y\n\n\
+                          data: incomplete";
+
+    /// Give the stream one byte at a time, so lines and `\r\n` are split
+    /// across reads.
+    struct Trickle(&'static [u8]);
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            match self.0.split_first() {
+                Some((&byte, rest)) => {
+                    buf[0] = byte;
+                    self.0 = rest;
+                    Poll::Ready(Ok(1))
+                }
+                None => Poll::Ready(Ok(0)),
+            }
+        }
+    }
+
+    fn event(event: Option<&str>, data: &str, id: Option<&str>, retry: Option<u64>) -> Event {
+        Event {
+            event: event.map(str::to_owned),
+            data: data.to_owned(),
+            id: id.map(str::to_owned),
+            retry,
+        }
+    }
+
+    fn parse(reader: impl AsyncRead + Unpin) -> Vec<Event> {
+        let events = EventStream::new(reader).map(Result::unwrap).collect();
+        futures_lite::future::block_on(events)
+    }
+
+    #[test]
+    fn parse_events() {
+        let expected = vec![
+            event(Some("greet"), "hello\n world", Some("1"), Some(3000)),
+            event(None, "second", Some("1"), None),
+            event(None, "", Some(""), None),
+            event(None, "x", Some(""), None),
+            event(None, "y", Some(""), None),
+        ];
+        assert_eq!(
+            parse(futures_lite::io::Cursor::new(STREAM.as_bytes())),
+            expected
+        );
+        assert_eq!(parse(Trickle(STREAM.as_bytes())), expected);
+    }
+
+    #[test]
+    fn read_errors_end_the_stream() {
+        struct Broken;
+
+        impl AsyncRead for Broken {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                _buf: &mut [u8],
+            ) -> Poll<std::io::Result<usize>> {
+                Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+            }
+        }
+
+        let reader = futures_lite::AsyncReadExt::chain(Trickle(b"data: a\n\ndata: b\n"), Broken);
+        let events = futures_lite::future::block_on(EventStream::new(reader).collect::<Vec<_>>());
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].as_ref().unwrap(), &event(None, "a", None, None));
+        assert_eq!(
+            events[1].as_ref().unwrap_err().kind(),
+            std::io::ErrorKind::ConnectionReset
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_lite::{AsyncRead, Stream};
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// A [`Stream`] of the chunks of a response body, created by
+/// [`crate::prelude::CommonResponse::bytes_stream`].
+///
+/// Each item is the data returned by one read of the response, so its size
+/// depends on how the data arrives, up to 8KB.
+#[derive(Debug)]
+pub struct BytesStream<R> {
+    reader: R,
+    finished: bool,
+}
+
+impl<R> BytesStream<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            finished: false,
+        }
+    }
+
+    /// Get back the underlying response.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for BytesStream<R> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        let mut chunk = vec![0; CHUNK_SIZE];
+        match Pin::new(&mut self.reader).poll_read(cx, &mut chunk) {
+            Poll::Ready(Ok(0)) => {
+                self.finished = true;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Ok(size)) => {
+                chunk.truncate(size);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Err(err)) => {
+                // The reader can't be trusted after an error
+                self.finished = true;
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A [`Stream`] of the values of a [JSON Lines](https://jsonlines.org) (or
+/// NDJSON) body, one per line, created by
+/// [`crate::prelude::CommonResponseSerdeExt::json_lines`].
+///
+/// Blank lines are skipped. A line that can't be deserialized yields an
+/// error, but the following lines are still parsed.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct JsonLines<R, T> {
+    reader: R,
+    buf: Vec<u8>,
+    /// How much of the buffer is known to have no line break.
+    scanned: usize,
+    finished: bool,
+    _item: std::marker::PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "serde")]
+impl<R, T> JsonLines<R, T> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::new(),
+            scanned: 0,
+            finished: false,
+            _item: std::marker::PhantomData,
+        }
+    }
+
+    /// Get back the underlying response.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<R: AsyncRead + Unpin, T: serde::de::DeserializeOwned> Stream for JsonLines<R, T> {
+    type Item = crate::DynResult<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(pos) = this.buf[this.scanned..].iter().position(|x| *x == b'\n') {
+                let end = this.scanned + pos;
+                let line: Vec<u8> = this.buf.drain(..=end).collect();
+                this.scanned = 0;
+                match parse_json_line(&line) {
+                    Some(item) => return Poll::Ready(Some(item)),
+                    None => continue,
+                }
+            }
+            this.scanned = this.buf.len();
+            if this.finished {
+                // The last line may not end with a line break
+                let line = std::mem::take(&mut this.buf);
+                this.scanned = 0;
+                return Poll::Ready(parse_json_line(&line));
+            }
+            let mut chunk = [0; CHUNK_SIZE];
+            match Pin::new(&mut this.reader).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => this.finished = true,
+                Poll::Ready(Ok(size)) => this.buf.extend_from_slice(&chunk[..size]),
+                Poll::Ready(Err(err)) => {
+                    // The reader can't be trusted after an error
+                    this.finished = true;
+                    this.buf.clear();
+                    return Poll::Ready(Some(Err(err.into())));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Deserialize a line, `None` if it's blank.
+#[cfg(feature = "serde")]
+fn parse_json_line<T: serde::de::DeserializeOwned>(line: &[u8]) -> Option<crate::DynResult<T>> {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+    Some(serde_json::from_slice(line).map_err(Into::into))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::StreamExt;
+
+    use super::*;
+
+    /// Give the body a few bytes at a time, so lines are split across reads.
+    struct Trickle(&'static [u8]);
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let size = self.0.len().min(buf.len()).min(3);
+            buf[..size].copy_from_slice(&self.0[..size]);
+            self.0 = &self.0[size..];
+            Poll::Ready(Ok(size))
+        }
+    }
+
+    #[test]
+    fn chunks_until_the_end() {
+        let chunks = futures_lite::future::block_on(
+            BytesStream::new(Trickle(b"hello world")).collect::<Vec<_>>(),
+        );
+        let chunks: Vec<_> = chunks.into_iter().map(Result::unwrap).collect();
+        assert_eq!(chunks, [&b"hel"[..], b"lo ", b"wor", b"ld"]);
+
+        let mut stream = BytesStream::new(futures_lite::io::Cursor::new(b"hello".to_vec()));
+        futures_lite::future::block_on(async {
+            assert_eq!(stream.next().await.unwrap().unwrap(), b"hello");
+            assert!(stream.next().await.is_none());
+            // Ended streams don't read again
+            assert!(stream.next().await.is_none());
+        });
+        assert_eq!(stream.into_inner().position(), 5);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_lines_split_across_reads() {
+        use serde_json::{json, Value};
+
+        let body = b"{\"id\": 1, \"name\": \"first\"}\n\
+                     \r\n\
+                     {\"id\": 2, \"name\": \"second\"}\r\n\
+                     not json\n\
+                     {\"id\": 3, \"name\": \"last\"}";
+        let items = futures_lite::future::block_on(
+            JsonLines::<_, Value>::new(Trickle(body)).collect::<Vec<_>>(),
+        );
+        assert_eq!(items.len(), 4);
+        assert_eq!(
+            items[0].as_ref().unwrap(),
+            &json!({"id": 1, "name": "first"})
+        );
+        assert_eq!(
+            items[1].as_ref().unwrap(),
+            &json!({"id": 2, "name": "second"})
+        );
+        assert!(items[2].is_err());
+        // The last line has no line break
+        assert_eq!(
+            items[3].as_ref().unwrap(),
+            &json!({"id": 3, "name": "last"})
+        );
+    }
+}
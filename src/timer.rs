@@ -0,0 +1,246 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    task::{Context, Poll, Wake, Waker},
+    time::{Duration, Instant},
+};
+
+/// A waker to be woken up at a point in time.
+struct TimerEntry {
+    at: Instant,
+    waker: Waker,
+}
+
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for TimerEntry {}
+
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerEntry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+/// The timer shared by all the waits, as we don't depend on any async
+/// runtime. A single thread sleeps until the earliest entry is due.
+#[derive(Default)]
+struct Timer {
+    entries: Mutex<BinaryHeap<Reverse<TimerEntry>>>,
+    changed: Condvar,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            std::thread::Builder::new()
+                .name("alhc-timer".into())
+                .spawn(|| Timer::get().run())
+                .expect("timer thread failed to start");
+            Timer::default()
+        })
+    }
+
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while entries.peek().is_some_and(|Reverse(entry)| entry.at <= now) {
+                due.extend(entries.pop().map(|Reverse(entry)| entry.waker));
+            }
+            if !due.is_empty() {
+                // Wake them without holding the lock, as they may schedule
+                // again right away
+                drop(entries);
+                due.into_iter().for_each(Waker::wake);
+                entries = self.entries.lock().unwrap();
+                continue;
+            }
+            entries = match entries.peek() {
+                Some(Reverse(entry)) => {
+                    let timeout = entry.at.saturating_duration_since(now);
+                    self.changed.wait_timeout(entries, timeout).unwrap().0
+                }
+                None => self.changed.wait(entries).unwrap(),
+            };
+        }
+    }
+
+    fn schedule(&self, at: Instant, waker: Waker) {
+        let mut entries = self.entries.lock().unwrap();
+        let earliest = entries.peek().is_none_or(|Reverse(entry)| at < entry.at);
+        entries.push(Reverse(TimerEntry { at, waker }));
+        // Only an earlier entry changes how long the thread sleeps
+        if earliest {
+            self.changed.notify_one();
+        }
+    }
+}
+
+/// Wake `waker` up at `until`, by the timer thread shared by all the waits.
+pub(crate) fn wake_at(until: Instant, waker: Waker) {
+    Timer::get().schedule(until, waker);
+}
+
+/// A future that resolves after a duration.
+#[derive(Debug)]
+pub(crate) struct Delay {
+    until: Instant,
+    armed: bool,
+}
+
+impl Delay {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            until: Instant::now() + duration,
+            armed: false,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.until {
+            return Poll::Ready(());
+        }
+        if !self.armed {
+            self.armed = true;
+            wake_at(self.until, cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// The time a whole request must be done by, including receiving its body,
+/// checked each time the request or its response is polled.
+#[derive(Debug)]
+pub(crate) struct Deadline {
+    at: Instant,
+    /// Woken up by the timer at the deadline, it wakes the task which has
+    /// polled last, so the timer is only scheduled once.
+    waker: Option<Arc<DeadlineWaker>>,
+}
+
+/// Forwards the wake up at the deadline to the latest polling task.
+#[derive(Debug, Default)]
+struct DeadlineWaker(Mutex<Option<Waker>>);
+
+impl Wake for DeadlineWaker {
+    fn wake(self: Arc<Self>) {
+        if let Some(waker) = self.0.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Clone for Deadline {
+    /// The clone schedules its own wake up, for the task polling it.
+    fn clone(&self) -> Self {
+        Self::new(self.at)
+    }
+}
+
+impl Deadline {
+    pub(crate) fn new(at: Instant) -> Self {
+        Self { at, waker: None }
+    }
+
+    /// Fail once the deadline has passed, or make sure the polling task is
+    /// woken up then, so a stalled transfer fails in time too.
+    pub(crate) fn check(&mut self, cx: &mut Context<'_>) -> std::io::Result<()> {
+        if Instant::now() >= self.at {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the request hasn't finished before its deadline",
+            ));
+        }
+        match &self.waker {
+            Some(waker) => {
+                let mut waker = waker.0.lock().unwrap();
+                if !waker.as_ref().is_some_and(|x| x.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+            }
+            None => {
+                let waker = Arc::new(DeadlineWaker(Mutex::new(Some(cx.waker().clone()))));
+                wake_at(self.at, Waker::from(waker.clone()));
+                self.waker = Some(waker);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The backoff before the `attempt`th connect retry, starting from 100ms and
+/// doubling each time up to 1.6s.
+pub(crate) fn connect_retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(100) * (1 << attempt.saturating_sub(1).min(4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_resolve_in_order() {
+        let start = Instant::now();
+        let finished = std::sync::Mutex::new(Vec::new());
+        futures_lite::future::block_on(futures_lite::future::zip(
+            async {
+                Delay::new(Duration::from_millis(80)).await;
+                finished.lock().unwrap().push(80);
+            },
+            async {
+                Delay::new(Duration::from_millis(20)).await;
+                finished.lock().unwrap().push(20);
+            },
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        assert_eq!(*finished.lock().unwrap(), [20, 80]);
+    }
+
+    #[test]
+    fn earlier_entry_wakes_the_timer() {
+        // A later entry is pending, the earlier one mustn't wait for it
+        wake_at(
+            Instant::now() + Duration::from_secs(60),
+            futures_lite::future::block_on(futures_lite::future::poll_fn(|cx| {
+                Poll::Ready(cx.waker().clone())
+            })),
+        );
+        let start = Instant::now();
+        futures_lite::future::block_on(Delay::new(Duration::from_millis(20)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn deadline_wakes_the_task() {
+        let mut deadline = Deadline::new(Instant::now() + Duration::from_millis(30));
+        let mut polls = 0;
+        let err = futures_lite::future::block_on(futures_lite::future::poll_fn(|cx| {
+            polls += 1;
+            match deadline.check(cx) {
+                Ok(()) => Poll::Pending,
+                Err(err) => Poll::Ready(err),
+            }
+        }));
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(polls, 2);
+    }
+}
@@ -0,0 +1,129 @@
+//! The `tracing` span of each request, which records nothing without the
+//! `tracing` feature.
+
+use crate::{Method, Timing};
+
+/// The span of a request, from when it's first polled until the body of its
+/// response has been read, with the tag of the request to tell its events
+/// apart from the ones of the other requests.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+#[cfg(feature = "tracing")]
+impl RequestSpan {
+    /// Start the span, once the request is complete so it knows its tag.
+    pub(crate) fn start(&mut self, method: Method, url: &str, tag: Option<&str>) {
+        if self.span.is_none() {
+            self.span = Some(tracing::debug_span!(
+                "request",
+                method = method.as_str(),
+                url,
+                tag
+            ));
+        }
+    }
+
+    /// The head of the response has been received.
+    pub(crate) fn response(&self, code: u16, timing: &Timing) {
+        if let Some(span) = &self.span {
+            tracing::debug!(
+                parent: span,
+                code,
+                time_to_first_byte_ms = timing.time_to_first_byte().as_millis() as u64,
+                "response received"
+            );
+        }
+    }
+
+    /// The whole body of the response has been received.
+    pub(crate) fn finished(&self, timing: &Timing) {
+        if let (Some(span), Some(total)) = (&self.span, timing.total()) {
+            tracing::debug!(
+                parent: span,
+                total_ms = total.as_millis() as u64,
+                "body received"
+            );
+        }
+    }
+
+    /// The request has failed.
+    pub(crate) fn failed(&self, err: &dyn std::fmt::Display) {
+        if let Some(span) = &self.span {
+            tracing::warn!(parent: span, error = %err, "request failed");
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl RequestSpan {
+    pub(crate) fn start(&mut self, _method: Method, _url: &str, _tag: Option<&str>) {}
+
+    pub(crate) fn response(&self, _code: u16, _timing: &Timing) {}
+
+    pub(crate) fn finished(&self, _timing: &Timing) {}
+
+    pub(crate) fn failed(&self, _err: &dyn std::fmt::Display) {}
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Log {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events_are_recorded_in_the_span_of_the_request() {
+        let log = Log::default();
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let sent_at = Instant::now();
+            let mut span = RequestSpan::default();
+            span.start(Method::GET, "http://example.com/", Some("index"));
+            // Polled again, the span is kept
+            span.start(Method::GET, "http://example.com/", None);
+            let mut timing = Timing::new(sent_at);
+            span.response(200, &timing);
+            timing.finish(sent_at);
+            span.finished(&timing);
+            span.failed(&"connection reset");
+        });
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3, "{log}");
+        for line in &lines {
+            assert!(
+                line.contains(r#"request{method="GET" url="http://example.com/" tag="index"}"#),
+                "{line}"
+            );
+        }
+        assert!(lines[0].contains("response received code=200 time_to_first_byte_ms="));
+        assert!(lines[1].contains("body received total_ms="));
+        assert!(lines[2].contains("WARN") && lines[2].contains("error=connection reset"));
+    }
+}
@@ -0,0 +1,821 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    io::Read,
+    os::raw::c_long,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use curl::{
+    easy::{Auth, Easy2, Handler, IpResolve, List, ReadError, SslVersion, WriteError},
+    multi::{Easy2Handle, Multi, MultiWaker},
+};
+use futures_lite::{AsyncRead, Future};
+use once_cell::sync::Lazy;
+
+use crate::{
+    error::tls_error,
+    response::{insert_header, split_raw_header},
+    HttpVersion, Method, TlsErrorReason, TlsInfo, TlsVersion,
+};
+
+use super::{BoxedReader, RequestBody, TransferOptions, TransferResponse};
+
+/// How much of the response body is buffered before the transfer is paused
+/// until it's read.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+/// The longest time the driver sleeps when curl has no timer running.
+const MAX_POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the driver thread waits for a new transfer once all of them are
+/// done before it exits, about how long curl keeps idle connections.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// `CURLOPT_SUPPRESS_CONNECT_HEADERS`, which `curl-sys` doesn't define.
+const CURLOPT_SUPPRESS_CONNECT_HEADERS: curl_sys::CURLoption = curl_sys::CURLOPTTYPE_LONG + 265;
+
+/// `CURLINFO_TLS_SSL_PTR`, which `curl-sys` doesn't define.
+#[cfg(feature = "openssl")]
+const CURLINFO_TLS_SSL_PTR: curl_sys::CURLINFO = curl_sys::CURLINFO_SLIST + 45;
+
+/// `CURLSSLBACKEND_OPENSSL` of `curl_sslbackend`.
+#[cfg(feature = "openssl")]
+const CURLSSLBACKEND_OPENSSL: std::os::raw::c_int = 1;
+
+/// `struct curl_tlssessioninfo`, which `curl-sys` doesn't define.
+#[cfg(feature = "openssl")]
+#[repr(C)]
+struct TlsSessionInfo {
+    backend: std::os::raw::c_int,
+    internals: *mut std::os::raw::c_void,
+}
+
+static DRIVER: Driver = Driver {
+    thread: Mutex::new(None),
+};
+
+static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Why a transfer has failed.
+#[derive(Debug)]
+pub(super) enum TransferError {
+    Curl(curl::Error),
+    /// The TLS handshake has failed, with the reason found out from the
+    /// error and the verification of the server certificate.
+    Tls(TlsErrorReason, curl::Error),
+    /// Reading the request body has failed, or the transfer couldn't be
+    /// started.
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Curl(err) | Self::Tls(_, err) => err.fmt(f),
+            Self::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Curl(err) | Self::Tls(_, err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<curl::Error> for TransferError {
+    fn from(err: curl::Error) -> Self {
+        Self::Curl(err)
+    }
+}
+
+impl From<TransferError> for std::io::Error {
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Io(err) => err,
+            TransferError::Tls(reason, _) => tls_error(reason),
+            err => std::io::Error::new(io_error_kind(&err), err),
+        }
+    }
+}
+
+enum Command {
+    Add(usize, Box<Easy2<TransferHandler>>),
+    /// Resume a transfer paused because its request body wasn't ready or its
+    /// response body buffer was full.
+    Unpause(usize),
+    /// Abort a transfer that's no longer wanted.
+    Remove(usize),
+}
+
+/// The thread running the transfers of all the clients on a curl multi
+/// handle, started with the first transfer and stopped once it has been idle
+/// for [`IDLE_TIMEOUT`].
+struct Driver {
+    thread: Mutex<Option<DriverThread>>,
+}
+
+struct DriverThread {
+    commands: Sender<Command>,
+    waker: MultiWaker,
+}
+
+impl DriverThread {
+    fn start() -> Self {
+        let (commands, receiver) = mpsc::channel();
+        let (waker_sender, waker_receiver) = mpsc::sync_channel(1);
+        std::thread::Builder::new()
+            .name("alhc-curl".into())
+            .spawn(move || {
+                let multi = Multi::new();
+                let _ = waker_sender.send(multi.waker());
+                run(multi, receiver);
+            })
+            .expect("curl thread failed to start");
+        Self {
+            commands,
+            waker: waker_receiver.recv().expect("curl thread failed to start"),
+        }
+    }
+}
+
+impl Driver {
+    fn send(&self, command: Command) {
+        let mut thread = self.thread.lock().unwrap();
+        // The thread only exits while holding the lock, so it can't miss the
+        // command once it's sent
+        let command = match &*thread {
+            Some(driver) => match driver.commands.send(command) {
+                Ok(()) => {
+                    let _ = driver.waker.wakeup();
+                    return;
+                }
+                Err(err) => err.0,
+            },
+            None => command,
+        };
+        let driver = thread.insert(DriverThread::start());
+        let _ = driver.commands.send(command);
+        let _ = driver.waker.wakeup();
+    }
+
+    /// Stop the thread if no command has been sent to it in the meantime,
+    /// otherwise give the command back.
+    fn stop(&self, commands: &Receiver<Command>) -> Option<Command> {
+        let mut thread = self.thread.lock().unwrap();
+        let command = commands.try_recv().ok();
+        if command.is_none() {
+            *thread = None;
+        }
+        command
+    }
+}
+
+fn run(multi: Multi, commands: Receiver<Command>) {
+    let mut transfers: HashMap<usize, Easy2Handle<TransferHandler>> = HashMap::new();
+    let mut finished = Vec::new();
+    loop {
+        if transfers.is_empty() {
+            // Nothing to drive, sleep until there's a new transfer
+            let command = match commands.recv_timeout(IDLE_TIMEOUT) {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => match DRIVER.stop(&commands) {
+                    Some(command) => command,
+                    None => return,
+                },
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            handle_command(&multi, &mut transfers, command);
+        }
+        for command in commands.try_iter() {
+            handle_command(&multi, &mut transfers, command);
+        }
+        let _ = multi.perform();
+        multi.messages(|message| {
+            let Ok(token) = message.token() else {
+                return;
+            };
+            if let Some(handle) = transfers.get(&token) {
+                if let Some(result) = message.result_for2(handle) {
+                    let state = &handle.get_ref().state;
+                    let result = result.map_err(|err| {
+                        match super::curl_tls_error_reason(&err, cert_verify_result(handle.raw())) {
+                            Some(reason) => TransferError::Tls(reason, err),
+                            None => TransferError::Curl(err),
+                        }
+                    });
+                    finish(state, result);
+                    finished.push(token);
+                }
+            }
+        });
+        for token in finished.drain(..) {
+            if let Some(handle) = transfers.remove(&token) {
+                let _ = multi.remove2(handle);
+            }
+        }
+        let timeout = match multi.get_timeout() {
+            Ok(Some(timeout)) => timeout.min(MAX_POLL_TIMEOUT),
+            _ => MAX_POLL_TIMEOUT,
+        };
+        let _ = multi.poll(&mut [], timeout);
+    }
+}
+
+fn handle_command(
+    multi: &Multi,
+    transfers: &mut HashMap<usize, Easy2Handle<TransferHandler>>,
+    command: Command,
+) {
+    match command {
+        Command::Add(token, easy) => {
+            let state = easy.get_ref().state.clone();
+            let added = multi
+                .add2(*easy)
+                .map_err(|err| std::io::Error::other(err.to_string()))
+                .and_then(|mut handle| {
+                    handle.set_token(token)?;
+                    Ok(handle)
+                });
+            match added {
+                Ok(handle) => {
+                    transfers.insert(token, handle);
+                }
+                Err(err) => finish(&state, Err(TransferError::Io(err))),
+            }
+        }
+        Command::Unpause(token) => {
+            if let Some(handle) = transfers.get(&token) {
+                let _ = handle.unpause_read();
+                let _ = handle.unpause_write();
+            }
+        }
+        Command::Remove(token) => {
+            if let Some(handle) = transfers.remove(&token) {
+                let _ = multi.remove2(handle);
+            }
+        }
+    }
+}
+
+fn finish(state: &Mutex<TransferState>, result: Result<(), TransferError>) {
+    let mut state = state.lock().unwrap();
+    state.done = true;
+    state.error = match result {
+        // Stopped on purpose, the rejection is the response
+        Err(_) if state.expectation_failed => None,
+        // The body has failed, not curl
+        Err(TransferError::Curl(err)) if err.is_aborted_by_callback() => state
+            .body_error
+            .take()
+            .map(TransferError::Io)
+            .or(Some(TransferError::Curl(err))),
+        result => result.err(),
+    };
+    state.wake();
+}
+
+/// The progress of a transfer, shared between the driver and the request or
+/// its response.
+#[derive(Default)]
+struct TransferState {
+    code: Option<u16>,
+    version: Option<HttpVersion>,
+    reason: Option<String>,
+    headers: HashMap<String, String>,
+    ordered_headers: Vec<(String, String)>,
+    raw_headers: Vec<(String, Vec<u8>)>,
+    tls_info: Option<TlsInfo>,
+    /// The headers received after the body.
+    trailers: HashMap<String, String>,
+    /// Whether the head of the final response has been received, so the
+    /// following data is its body.
+    head_done: bool,
+    /// Whether the server has rejected `Expect: 100-continue` with `417`,
+    /// the transfer is stopped then without the body of the response.
+    expectation_failed: bool,
+    body: VecDeque<u8>,
+    /// Whether curl is waiting for the buffered body to be read.
+    recv_paused: bool,
+    done: bool,
+    error: Option<TransferError>,
+    body_error: Option<std::io::Error>,
+    waker: Option<Waker>,
+}
+
+impl TransferState {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The TLS version and cipher suite of the connection of the transfer, only
+/// known if curl is built with the OpenSSL this crate is linked to.
+#[cfg(feature = "openssl")]
+fn tls_info(easy: *mut curl_sys::CURL) -> Option<TlsInfo> {
+    let mut session: *const TlsSessionInfo = std::ptr::null();
+    let code = unsafe { curl_sys::curl_easy_getinfo(easy, CURLINFO_TLS_SSL_PTR, &mut session) };
+    if code != curl_sys::CURLE_OK || session.is_null() {
+        return None;
+    }
+    let session = unsafe { &*session };
+    // Without TLS there's no session
+    if session.backend != CURLSSLBACKEND_OPENSSL || session.internals.is_null() {
+        return None;
+    }
+    let ssl = session.internals as *const openssl_sys::SSL;
+    let version = unsafe { std::ffi::CStr::from_ptr(openssl_sys::SSL_get_version(ssl)) };
+    let version = match version.to_bytes() {
+        b"TLSv1" => TlsVersion::Tls1_0,
+        b"TLSv1.1" => TlsVersion::Tls1_1,
+        b"TLSv1.2" => TlsVersion::Tls1_2,
+        b"TLSv1.3" => TlsVersion::Tls1_3,
+        _ => return None,
+    };
+    let cipher = unsafe { openssl_sys::SSL_get_current_cipher(ssl) };
+    if cipher.is_null() {
+        return None;
+    }
+    let cipher = unsafe { std::ffi::CStr::from_ptr(openssl_sys::SSL_CIPHER_get_name(cipher)) };
+    Some(TlsInfo {
+        version,
+        cipher: cipher.to_string_lossy().into_owned(),
+    })
+}
+
+#[cfg(not(feature = "openssl"))]
+fn tls_info(_easy: *mut curl_sys::CURL) -> Option<TlsInfo> {
+    None
+}
+
+/// The result of verifying the server certificate of the transfer, only
+/// known if curl is built with OpenSSL or one of its forks, whose
+/// `X509_V_ERR_*` codes it is.
+fn cert_verify_result(easy: *mut curl_sys::CURL) -> Option<c_long> {
+    static OPENSSL: Lazy<bool> = Lazy::new(|| {
+        curl::Version::get().ssl_version().is_some_and(|version| {
+            ["OpenSSL", "LibreSSL", "BoringSSL", "quictls", "AWS-LC"]
+                .iter()
+                .any(|name| version.contains(name))
+        })
+    });
+    if !*OPENSSL {
+        return None;
+    }
+    let mut result: c_long = 0;
+    let code = unsafe {
+        curl_sys::curl_easy_getinfo(easy, curl_sys::CURLINFO_SSL_VERIFYRESULT, &mut result)
+    };
+    (code == curl_sys::CURLE_OK).then_some(result)
+}
+
+/// Resumes the transfer when the request body it's waiting for can be read.
+struct Unpause(usize);
+
+impl Wake for Unpause {
+    fn wake(self: Arc<Self>) {
+        DRIVER.send(Command::Unpause(self.0));
+    }
+}
+
+/// The easy handle of a transfer, only used by the driver thread from the
+/// callbacks of the transfer.
+struct EasyPtr(*mut curl_sys::CURL);
+
+unsafe impl Send for EasyPtr {}
+
+struct TransferHandler {
+    state: Arc<Mutex<TransferState>>,
+    easy: EasyPtr,
+    body: Option<BoxedReader>,
+    body_waker: Waker,
+    /// Whether curl answers the `407` challenges of the proxy by itself, so
+    /// they aren't the final response.
+    proxy_auth: bool,
+    /// Whether curl answers the `401` challenges of the server by itself.
+    server_auth: bool,
+    /// Whether the body is only sent once the server answers `100 Continue`.
+    expect_continue: bool,
+}
+
+impl Handler for TransferHandler {
+    fn header(&mut self, data: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let line = String::from_utf8_lossy(data);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if state.head_done {
+            // The trailers after the body of the final response
+            if let Some((name, value)) = line.split_once(':') {
+                state
+                    .trailers
+                    .insert(name.trim().to_lowercase(), value.trim().to_owned());
+            }
+        } else if line.is_empty() {
+            match state.code {
+                // Informational responses and handled challenges are followed
+                // by another response
+                Some(code)
+                    if code >= 200
+                        && !(self.proxy_auth && code == 407)
+                        && !(self.server_auth && code == 401) =>
+                {
+                    state.tls_info = tls_info(self.easy.0);
+                    state.head_done = true;
+                    // curl would send the request again with the same
+                    // expectation, stop it and give the rejection back
+                    state.expectation_failed = code == 417 && self.expect_continue;
+                    state.wake();
+                    return !state.expectation_failed;
+                }
+                _ => {}
+            }
+        } else if let Some(status) = line.strip_prefix("HTTP/") {
+            // A new response begins, forget the previous one
+            let mut parts = status.splitn(3, ' ');
+            state.version = parts.next().and_then(HttpVersion::parse);
+            state.code = parts.next().and_then(|x| x.parse().ok());
+            state.reason = parts
+                .next()
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::to_owned);
+            state.headers.clear();
+            state.ordered_headers.clear();
+            state.raw_headers.clear();
+        } else if let Some((name, value)) = line.split_once(':') {
+            let (name, value) = (name.trim(), value.trim());
+            insert_header(&mut state.headers, name.to_lowercase(), value);
+            state
+                .ordered_headers
+                .push((name.to_owned(), value.to_owned()));
+            state.raw_headers.extend(split_raw_header(data));
+        }
+        true
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
+        let mut state = self.state.lock().unwrap();
+        if state.body.len() >= MAX_BUFFERED {
+            state.recv_paused = true;
+            return Err(WriteError::Pause);
+        }
+        state.head_done = true;
+        state.body.extend(data);
+        state.wake();
+        Ok(data.len())
+    }
+
+    fn read(&mut self, data: &mut [u8]) -> Result<usize, ReadError> {
+        let Some(body) = &mut self.body else {
+            return Ok(0);
+        };
+        let mut cx = Context::from_waker(&self.body_waker);
+        match Pin::new(body).poll_read(&mut cx, data) {
+            Poll::Ready(Ok(size)) => Ok(size),
+            Poll::Ready(Err(err)) => {
+                self.state.lock().unwrap().body_error = Some(err);
+                Err(ReadError::Abort)
+            }
+            Poll::Pending => Err(ReadError::Pause),
+        }
+    }
+}
+
+/// A request sent by curl, until the head of its response is received.
+pub(super) struct Transfer {
+    token: usize,
+    state: Option<Arc<Mutex<TransferState>>>,
+}
+
+/// The body of a response received by curl.
+pub(super) struct TransferBody {
+    token: usize,
+    state: Arc<Mutex<TransferState>>,
+}
+
+/// Abort the transfer if it's still running.
+fn abort(token: usize, state: &Mutex<TransferState>) {
+    if !state.lock().unwrap().done {
+        DRIVER.send(Command::Remove(token));
+    }
+}
+
+pub(super) fn send(
+    options: &TransferOptions<'_>,
+    body: RequestBody,
+) -> Result<Transfer, TransferError> {
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let state = Arc::new(Mutex::new(TransferState::default()));
+    // An empty body given explicitly still sends its length
+    let empty_sized = matches!(body, RequestBody::Sized(_, 0));
+    let (body, body_size) = match body {
+        RequestBody::Empty => (None, None),
+        RequestBody::Sized(_, 0) => (None, None),
+        RequestBody::Sized(body, size) => (Some(body), Some(size)),
+        RequestBody::Chunked(body) => (Some(body), None),
+    };
+    let has_body = body.is_some();
+    let expect_continue = has_body
+        && options.headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("Expect") && value.eq_ignore_ascii_case("100-continue")
+        });
+    let mut easy = Easy2::new(TransferHandler {
+        state: state.clone(),
+        easy: EasyPtr(std::ptr::null_mut()),
+        body,
+        body_waker: Waker::from(Arc::new(Unpause(token))),
+        proxy_auth: options.proxy_credentials.is_some(),
+        server_auth: options.digest_credentials.is_some(),
+        expect_continue,
+    });
+    easy.get_mut().easy = EasyPtr(easy.raw());
+    // curl ties its behavior to the method, like how the body is sent
+    match (options.method, has_body) {
+        (Method::GET, false) => easy.get(true)?,
+        (Method::HEAD, false) => easy.nobody(true)?,
+        (Method::POST, _) => easy.post(true)?,
+        (Method::PUT, true) => easy.upload(true)?,
+        (method, has_body) => {
+            easy.upload(has_body)?;
+            easy.custom_request(method.as_str())?;
+        }
+    }
+    easy.url(options.url)?;
+    let mut headers = List::new();
+    for (name, value) in options.headers {
+        if value.is_empty() {
+            // curl drops headers ending with a colon
+            headers.append(&format!("{};", name))?;
+        } else {
+            headers.append(&format!("{}: {}", name, value))?;
+        }
+    }
+    for name in options.removed_headers {
+        // curl doesn't send its own header when given one without a value
+        headers.append(&format!("{}:", name))?;
+    }
+    let is_post = matches!(options.method, Method::POST);
+    let has_header = |header: &str| {
+        options
+            .headers
+            .iter()
+            .map(|(name, _)| name)
+            .chain(options.removed_headers)
+            .any(|name| name.eq_ignore_ascii_case(header))
+    };
+    // Don't let curl make up a form content type or an expectation the
+    // request didn't ask for
+    if is_post && !has_header("Content-Type") {
+        headers.append("Content-Type:")?;
+    }
+    if has_body && !has_header("Expect") {
+        headers.append("Expect:")?;
+    }
+    match body_size {
+        Some(size) if is_post => easy.post_field_size(size)?,
+        Some(size) => easy.in_filesize(size)?,
+        None if has_body => headers.append("Transfer-Encoding: chunked")?,
+        // Strict servers want the length of an empty body too
+        None if is_post => easy.post_field_size(0)?,
+        None if empty_sized && !has_header("Content-Length") => {
+            headers.append("Content-Length: 0")?
+        }
+        None => {}
+    }
+    easy.http_headers(headers)?;
+    if let Some(timeout) = options.timeout {
+        easy.timeout(timeout)?;
+    }
+    if options.forbid_reuse {
+        easy.forbid_reuse(true)?;
+    }
+    if options.decompress {
+        // An empty value enables all the encodings curl supports
+        easy.accept_encoding("")?;
+    }
+    if let Some(key) = options.pinned_public_key {
+        easy.pinned_public_key(&format!("sha256//{}", base64(&key)))?;
+    }
+    if let Some(version) = options.min_tls_version {
+        let version = match version {
+            TlsVersion::Tls1_0 => SslVersion::Tlsv10,
+            TlsVersion::Tls1_1 => SslVersion::Tlsv11,
+            TlsVersion::Tls1_2 => SslVersion::Tlsv12,
+            TlsVersion::Tls1_3 => SslVersion::Tlsv13,
+        };
+        easy.ssl_min_max_version(version, SslVersion::Default)?;
+    }
+    if options.http1_only {
+        easy.http_version(curl::easy::HttpVersion::V11)?;
+    }
+    if let Some(addr) = options.local_address {
+        easy.interface(&format!("host!{}", addr))?;
+    }
+    match options.ip_version {
+        crate::IpVersion::Auto => {}
+        crate::IpVersion::V4Only => easy.ip_resolve(IpResolve::V4)?,
+        crate::IpVersion::V6Only => easy.ip_resolve(IpResolve::V6)?,
+    }
+    if let Some(env_proxy) = options.env_proxy {
+        if let Some(proxy) = env_proxy.for_url(options.url) {
+            easy.proxy(proxy)?;
+        }
+        easy.noproxy(&env_proxy.no_proxy.join(","))?;
+    }
+    if let Some((user, pass)) = options.proxy_credentials {
+        easy.proxy_auth(Auth::new().basic(true).digest(true))?;
+        easy.proxy_username(user)?;
+        easy.proxy_password(pass)?;
+    }
+    if let Some((user, pass)) = options.digest_credentials {
+        easy.http_auth(Auth::new().digest(true))?;
+        easy.username(user)?;
+        easy.password(pass)?;
+    }
+    // The response to the CONNECT of a proxy tunnel isn't the final response
+    let code = unsafe {
+        curl_sys::curl_easy_setopt(easy.raw(), CURLOPT_SUPPRESS_CONNECT_HEADERS, 1 as c_long)
+    };
+    if code != curl_sys::CURLE_OK {
+        return Err(curl::Error::new(code).into());
+    }
+    DRIVER.send(Command::Add(token, Box::new(easy)));
+    Ok(Transfer {
+        token,
+        state: Some(state),
+    })
+}
+
+impl Future for Transfer {
+    type Output = Result<TransferResponse, TransferError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(shared) = &self.state else {
+            return Poll::Ready(Err(TransferError::Io(std::io::Error::other(
+                "already polled",
+            ))));
+        };
+        let mut state = shared.lock().unwrap();
+        let finished = state.done && state.error.is_none() && state.code.is_some();
+        if !state.head_done && !finished {
+            if state.done {
+                let err = state.error.take().unwrap_or_else(|| {
+                    TransferError::Io(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "the server sent no response",
+                    ))
+                });
+                return Poll::Ready(Err(err));
+            }
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let head = TransferResponse {
+            code: state.code.unwrap_or_default(),
+            version: state.version.unwrap_or(HttpVersion::Http11),
+            reason: state.reason.take().map(Cow::Owned),
+            headers: std::mem::take(&mut state.headers),
+            ordered_headers: std::mem::take(&mut state.ordered_headers),
+            raw_headers: std::mem::take(&mut state.raw_headers),
+            tls_info: state.tls_info.take(),
+            body_discarded: state.expectation_failed,
+            body: TransferBody {
+                token: self.token,
+                state: shared.clone(),
+            },
+        };
+        drop(state);
+        // The body takes over the transfer
+        self.state = None;
+        Poll::Ready(Ok(head))
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if let Some(state) = &self.state {
+            abort(self.token, state);
+        }
+    }
+}
+
+impl AsyncRead for TransferBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.body.is_empty() {
+            let size = state.body.read(buf)?;
+            if state.recv_paused && state.body.len() < MAX_BUFFERED / 2 {
+                state.recv_paused = false;
+                DRIVER.send(Command::Unpause(self.token));
+            }
+            return Poll::Ready(Ok(size));
+        }
+        if state.done {
+            return Poll::Ready(match state.error.take() {
+                Some(err) => Err(err.into()),
+                None => Ok(0),
+            });
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl TransferBody {
+    /// The trailers received after the body, only known once the body has
+    /// been read to the end.
+    pub(super) fn trailers(&self) -> HashMap<String, String> {
+        std::mem::take(&mut self.state.lock().unwrap().trailers)
+    }
+}
+
+impl Drop for TransferBody {
+    fn drop(&mut self) {
+        abort(self.token, &self.state);
+    }
+}
+
+/// Encode `data` in base64 with padding, as curl expects the pinned key hash.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, x)| bits | (*x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Whether the failure happened while connecting, before anything was sent.
+pub(super) fn is_connect_failure(err: &TransferError) -> bool {
+    matches!(err, TransferError::Curl(err) if err.is_couldnt_connect() || err.is_ssl_connect_error())
+}
+
+/// Why the TLS handshake failed, if it's the failure.
+pub(super) fn tls_error_reason(err: &TransferError) -> Option<TlsErrorReason> {
+    match err {
+        TransferError::Tls(reason, _) => Some(*reason),
+        _ => None,
+    }
+}
+
+/// The kind of the I/O error behind the failure, to be matched against the
+/// retry policy.
+pub(super) fn io_error_kind(err: &TransferError) -> std::io::ErrorKind {
+    match err {
+        TransferError::Io(err) => err.kind(),
+        TransferError::Curl(err) if err.is_couldnt_connect() || err.is_ssl_connect_error() => {
+            std::io::ErrorKind::ConnectionRefused
+        }
+        TransferError::Curl(err) if err.is_operation_timedout() => std::io::ErrorKind::TimedOut,
+        TransferError::Curl(err)
+            if err.is_couldnt_resolve_host() || err.is_couldnt_resolve_proxy() =>
+        {
+            std::io::ErrorKind::NotFound
+        }
+        TransferError::Curl(err) if err.is_partial_file() => std::io::ErrorKind::UnexpectedEof,
+        // Failures of the transfer after connected, like a reset connection
+        TransferError::Curl(err)
+            if err.is_send_error() || err.is_recv_error() || err.is_got_nothing() =>
+        {
+            std::io::ErrorKind::ConnectionReset
+        }
+        TransferError::Tls(..) => std::io::ErrorKind::PermissionDenied,
+        TransferError::Curl(_) => std::io::ErrorKind::Other,
+    }
+}
+
+/// Whether reading the body failed because the connection closed before the
+/// whole body was received.
+pub(super) fn is_partial_transfer(err: &std::io::Error) -> bool {
+    matches!(
+        err.get_ref().and_then(|x| x.downcast_ref::<TransferError>()),
+        Some(TransferError::Curl(err)) if err.is_partial_file()
+    )
+}
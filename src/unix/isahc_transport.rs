@@ -0,0 +1,219 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_lite::{AsyncRead, Future};
+use isahc::{
+    auth::{Authentication, Credentials},
+    config::{Configurable, IpVersion, VersionNegotiation},
+    AsyncBody, HttpClient, ResponseExt, ResponseFuture, Trailer,
+};
+use once_cell::sync::Lazy;
+
+use crate::{response::insert_header, HttpVersion, Method, TlsErrorReason};
+
+use super::{RequestBody, TransferOptions, TransferResponse};
+
+static SHARED: Lazy<HttpClient> =
+    Lazy::new(|| HttpClient::new().expect("shared client failed to initialize"));
+
+pub(super) type TransferError = isahc::Error;
+
+/// The body of a response received by isahc.
+pub(super) struct TransferBody {
+    body: AsyncBody,
+    trailer: Trailer,
+}
+
+impl AsyncRead for TransferBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.body).poll_read(cx, buf)
+    }
+}
+
+impl TransferBody {
+    /// The trailers received after the body, only known once the body has
+    /// been read to the end.
+    pub(super) fn trailers(&self) -> HashMap<String, String> {
+        let Some(trailer) = self.trailer.try_get() else {
+            return HashMap::new();
+        };
+        trailer
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// A request sent by isahc, until the head of its response is received.
+pub(super) struct Transfer(ResponseFuture<'static>);
+
+pub(super) fn send(
+    options: &TransferOptions<'_>,
+    body: RequestBody,
+) -> Result<Transfer, TransferError> {
+    // isahc doesn't tell the length of an empty body, which strict servers
+    // want for a POST
+    let empty_post = matches!(body, RequestBody::Empty) && matches!(options.method, Method::POST);
+    let body = match body {
+        RequestBody::Empty => AsyncBody::empty(),
+        RequestBody::Sized(body, size) => AsyncBody::from_reader_sized(body, size),
+        // isahc falls back to chunked transfer for unsized bodies
+        RequestBody::Chunked(body) => AsyncBody::from_reader(body),
+    };
+    let mut req_builder = isahc::http::request::Builder::new()
+        .method(options.method.as_str())
+        .uri(options.url);
+    for (header, value) in options.headers {
+        req_builder = req_builder.header(header.as_str(), value.as_str());
+    }
+    if empty_post {
+        req_builder = req_builder.header("Content-Length", "0");
+    }
+    if options.forbid_reuse {
+        // isahc only forbids reusing connections for a whole client
+        req_builder = req_builder.header("Connection", "close");
+    }
+    req_builder = req_builder.automatic_decompression(options.decompress);
+    if let Some(timeout) = options.timeout {
+        req_builder = req_builder.timeout(timeout);
+    }
+    if options.http1_only {
+        req_builder = req_builder.version_negotiation(VersionNegotiation::http11());
+    }
+    if let Some(addr) = options.local_address {
+        req_builder = req_builder.interface(addr);
+    }
+    match options.ip_version {
+        crate::IpVersion::Auto => {}
+        crate::IpVersion::V4Only => req_builder = req_builder.ip_version(IpVersion::V4),
+        crate::IpVersion::V6Only => req_builder = req_builder.ip_version(IpVersion::V6),
+    }
+    if let Some(env_proxy) = options.env_proxy {
+        if let Some(proxy) = env_proxy
+            .for_url(options.url)
+            .and_then(|x| x.parse::<isahc::http::Uri>().ok())
+        {
+            req_builder = req_builder.proxy(proxy);
+        }
+        req_builder = req_builder.proxy_blacklist(env_proxy.no_proxy.iter());
+    }
+    if let Some((user, pass)) = options.proxy_credentials {
+        req_builder = req_builder
+            .proxy_authentication(Authentication::all())
+            .proxy_credentials(Credentials::new(user.as_str(), pass.as_str()));
+    }
+    if let Some((user, pass)) = options.digest_credentials {
+        req_builder = req_builder
+            .authentication(Authentication::digest())
+            .credentials(Credentials::new(user.as_str(), pass.as_str()));
+    }
+    let req = req_builder.body(body)?;
+    Ok(Transfer(SHARED.send_async(req)))
+}
+
+impl Future for Transfer {
+    type Output = Result<TransferResponse, TransferError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let res = match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(Ok(res)) => res,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+        let mut headers = HashMap::with_capacity(res.headers().len());
+        let mut ordered_headers = Vec::with_capacity(res.headers().len());
+        let mut raw_headers = Vec::with_capacity(res.headers().len());
+        for (name, value) in res.headers().iter() {
+            raw_headers.push((name.as_str().to_string(), value.as_bytes().to_vec()));
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            insert_header(&mut headers, name.as_str().to_string(), &value);
+            ordered_headers.push((name.as_str().to_string(), value));
+        }
+        Poll::Ready(Ok(TransferResponse {
+            code: res.status().as_u16(),
+            version: match res.version() {
+                isahc::http::Version::HTTP_10 => HttpVersion::Http10,
+                isahc::http::Version::HTTP_2 => HttpVersion::Http2,
+                isahc::http::Version::HTTP_3 => HttpVersion::Http3,
+                _ => HttpVersion::Http11,
+            },
+            // The http crate doesn't keep the reason phrase sent by the server
+            reason: res.status().canonical_reason().map(Cow::Borrowed),
+            headers,
+            ordered_headers,
+            raw_headers,
+            // isahc doesn't expose the TLS session
+            tls_info: None,
+            body_discarded: false,
+            body: TransferBody {
+                trailer: res.trailer().clone(),
+                body: res.into_body(),
+            },
+        }))
+    }
+}
+
+/// Whether the failure happened while connecting, before anything was sent.
+pub(super) fn is_connect_failure(err: &TransferError) -> bool {
+    err.kind() == isahc::error::ErrorKind::ConnectionFailed
+}
+
+/// Find out why the TLS handshake failed from the curl error behind it.
+///
+/// isahc doesn't tell the result of verifying the server certificate, so a
+/// rejected one is only reported as [`TlsErrorReason::InvalidCert`].
+pub(super) fn tls_error_reason(err: &TransferError) -> Option<TlsErrorReason> {
+    let curl_error = std::error::Error::source(err).and_then(|x| x.downcast_ref::<curl::Error>());
+    if let Some(reason) = curl_error.and_then(|x| super::curl_tls_error_reason(x, None)) {
+        return Some(reason);
+    }
+    match err.kind() {
+        isahc::error::ErrorKind::BadServerCertificate => Some(TlsErrorReason::InvalidCert),
+        isahc::error::ErrorKind::BadClientCertificate => Some(TlsErrorReason::ClientCertRequired),
+        isahc::error::ErrorKind::TlsEngine => Some(TlsErrorReason::Channel),
+        _ => None,
+    }
+}
+
+/// The kind of the I/O error behind the failure, to be matched against the
+/// retry policy.
+pub(super) fn io_error_kind(err: &TransferError) -> std::io::ErrorKind {
+    match err.kind() {
+        isahc::error::ErrorKind::ConnectionFailed => std::io::ErrorKind::ConnectionRefused,
+        isahc::error::ErrorKind::Timeout => std::io::ErrorKind::TimedOut,
+        isahc::error::ErrorKind::NameResolution => std::io::ErrorKind::NotFound,
+        isahc::error::ErrorKind::BadServerCertificate
+        | isahc::error::ErrorKind::BadClientCertificate
+        | isahc::error::ErrorKind::TlsEngine => std::io::ErrorKind::PermissionDenied,
+        // Failures of the transfer after connected, like a reset connection
+        isahc::error::ErrorKind::Io => std::error::Error::source(err)
+            .and_then(|x| x.downcast_ref::<std::io::Error>())
+            .map(std::io::Error::kind)
+            .unwrap_or(std::io::ErrorKind::ConnectionReset),
+        _ => std::io::ErrorKind::Other,
+    }
+}
+
+/// Whether reading the body failed because the connection closed before the
+/// whole body was received.
+pub(super) fn is_partial_transfer(err: &std::io::Error) -> bool {
+    err.get_ref()
+        .and_then(|x| x.downcast_ref::<isahc::Error>())
+        .and_then(std::error::Error::source)
+        .and_then(|x| x.downcast_ref::<curl::Error>())
+        .map(curl::Error::is_partial_file)
+        .unwrap_or(false)
+}
@@ -1,39 +1,871 @@
 //! Platform specific implementation for Unix (Linux and macOS)
 //!
-//! Currently using [`isahc` crate](https://github.com/sagebind/isahc) for compability,
-//! will be replaced by simpler code directly using [`curl` crate](https://github.com/alexcrichton/curl-rust).
+//! Currently using system libcurl through [`curl` crate](https://github.com/alexcrichton/curl-rust),
+//! whose multi interface is driven by a background thread shared by all the clients.
+//!
+//! The `isahc` feature sends the requests through [`isahc` crate](https://github.com/sagebind/isahc)
+//! instead, like the older versions did.
 
+#[cfg(not(feature = "isahc"))]
+mod curl_transport;
+#[cfg(feature = "isahc")]
+mod isahc_transport;
 mod request;
 mod response;
 
+#[cfg(not(feature = "isahc"))]
+use curl_transport as transport;
+#[cfg(feature = "isahc")]
+use isahc_transport as transport;
+
 pub use request::CURLRequest;
 pub use response::CURLResponse;
 
-use isahc::HttpClient;
-use once_cell::sync::Lazy;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_lite::AsyncRead;
 
 use crate::{
+    concurrency::ConcurrencyLimiter,
+    error::validate_url,
     prelude::{CommonClient, CommonClientBuilder},
-    Client, ClientBuilder,
+    proxy::EnvProxy,
+    rate_limit::RateLimiter,
+    Client, ClientBuilder, IpVersion, Method, TlsErrorReason, TlsVersion,
 };
 
-pub(super) static SHARED: Lazy<HttpClient> =
-    Lazy::new(|| HttpClient::new().expect("shared client failed to initialize"));
+type BoxedReader = Box<dyn AsyncRead + Unpin + Send + Sync + 'static>;
+
+/// The body of a request handed over to the transport.
+enum RequestBody {
+    Empty,
+    Sized(BoxedReader, u64),
+    /// Sent with chunked transfer encoding, as its size is unknown.
+    Chunked(BoxedReader),
+}
+
+/// The options of a request handed over to the transport.
+struct TransferOptions<'a> {
+    method: Method,
+    url: &'a str,
+    headers: &'a [(String, String)],
+    /// isahc always sends the headers it adds by itself
+    #[cfg_attr(feature = "isahc", allow(dead_code))]
+    removed_headers: &'a [String],
+    timeout: Option<Duration>,
+    http1_only: bool,
+    local_address: Option<IpAddr>,
+    ip_version: IpVersion,
+    env_proxy: Option<&'a EnvProxy>,
+    proxy_credentials: Option<&'a (String, String)>,
+    /// The credentials to answer Digest challenges of the server with.
+    digest_credentials: Option<&'a (String, String)>,
+    /// Close the connection once the request is done.
+    forbid_reuse: bool,
+    decompress: bool,
+    #[cfg_attr(feature = "isahc", allow(dead_code))]
+    pinned_public_key: Option<[u8; 32]>,
+    #[cfg_attr(feature = "isahc", allow(dead_code))]
+    min_tls_version: Option<TlsVersion>,
+}
+
+/// The head of the final response received by the transport.
+struct TransferResponse {
+    code: u16,
+    version: crate::HttpVersion,
+    reason: Option<Cow<'static, str>>,
+    headers: HashMap<String, String>,
+    /// The headers in the order they were received.
+    ordered_headers: Vec<(String, String)>,
+    /// The headers with their values as the bytes received.
+    raw_headers: Vec<(String, Vec<u8>)>,
+    tls_info: Option<crate::TlsInfo>,
+    /// Whether the transport has dropped the body of the response, like the
+    /// one of a rejected `Expect: 100-continue`.
+    body_discarded: bool,
+    body: transport::TransferBody,
+}
+
+/// The requests in flight of a client to each host, so the ones beyond
+/// [`ClientBuilder::pool_max_idle_per_host`] close their connections.
+#[derive(Debug, Default)]
+pub(crate) struct HostUses(Mutex<HashMap<String, Arc<()>>>);
+
+impl HostUses {
+    /// Count a request to the host of `url` in flight until the returned
+    /// token is dropped, along with how many other requests to the host are.
+    fn start(&self, url: &str) -> (Arc<()>, usize) {
+        let mut hosts = self.0.lock().unwrap();
+        hosts.retain(|_, token| Arc::strong_count(token) > 1);
+        let token = hosts.entry(host_of(url).to_owned()).or_default().clone();
+        // Minus the one of the map and the new one
+        let others = Arc::strong_count(&token) - 2;
+        (token, others)
+    }
+}
+
+/// The host and port of the URL, which curl keeps connections for.
+fn host_of(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority.rsplit('@').next().unwrap_or_default()
+}
 
 impl CommonClient for Client {
     type ClientRequest = CURLRequest;
 
+    fn set_timeout(&mut self, max_timeout: Duration) {
+        self.timeout = Some(max_timeout);
+    }
+
     fn request(&self, method: crate::Method, url: &str) -> crate::DynResult<Self::ClientRequest> {
-        Ok(CURLRequest::new(
-            isahc::http::request::Builder::new()
-                .method(method.as_str())
-                .uri(url),
-        ))
+        validate_url(url)?;
+        Ok(CURLRequest::new(method, url, self))
     }
 }
 
 impl CommonClientBuilder for ClientBuilder {
     fn build(&self) -> crate::DynResult<crate::Client> {
-        Ok(Client {})
+        #[cfg(feature = "isahc")]
+        if self.pinned_public_key.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "pinning the public key isn't supported by isahc",
+            )
+            .into());
+        }
+        #[cfg(feature = "isahc")]
+        if self.min_tls_version.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "setting the minimum TLS version isn't supported by isahc",
+            )
+            .into());
+        }
+        if let Some(addr) = self.local_address {
+            // Binding fails if the address doesn't belong to this host
+            if let Err(err) = UdpSocket::bind((addr, 0)) {
+                return Err(std::io::Error::new(
+                    err.kind(),
+                    format!("local address {} isn't assignable: {}", addr, err),
+                )
+                .into());
+            }
+        }
+        Ok(Client {
+            timeout: self.default_timeout,
+            http1_only: self.http1_only,
+            local_address: self.local_address,
+            ip_version: self.ip_version,
+            env_proxy: if self.proxy_from_env {
+                EnvProxy::read().map(Arc::new)
+            } else {
+                None
+            },
+            connect_retries: self.connect_retries,
+            pool_max_idle_per_host: self.pool_max_idle_per_host,
+            allow_truncated_body: self.allow_truncated_body,
+            max_response_size: self.max_response_size,
+            pinned_public_key: self.pinned_public_key,
+            min_tls_version: self.min_tls_version,
+            host_uses: Arc::default(),
+            auto_decompress: self.auto_decompress,
+            proxy_credentials: self.proxy_credentials.clone().map(Arc::new),
+            retry: self.retry.clone().map(Arc::new),
+            rate_limiter: self.rate_limit.map(|x| Arc::new(RateLimiter::new(x))),
+            concurrency_limiter: self
+                .max_concurrent
+                .map(|x| Arc::new(ConcurrencyLimiter::new(x))),
+            #[cfg(feature = "gzip")]
+            compress_threshold: self.compress_threshold,
+            requests: Arc::default(),
+        })
+    }
+}
+
+/// Find out why the TLS handshake failed from the code of the curl error and
+/// the result of verifying the server certificate, an OpenSSL `X509_V_ERR_*`
+/// code, if it's known.
+fn curl_tls_error_reason(
+    err: &curl::Error,
+    verify_result: Option<std::os::raw::c_long>,
+) -> Option<TlsErrorReason> {
+    if err.code() == curl_sys::CURLE_SSL_PINNEDPUBKEYNOTMATCH {
+        Some(TlsErrorReason::PinnedKeyMismatch)
+    } else if err.is_ssl_certproblem() || err.is_ssl_cacert_badfile() {
+        Some(TlsErrorReason::ClientCertRequired)
+    } else if err.is_peer_failed_verification() {
+        Some(verify_result.map_or(TlsErrorReason::InvalidCert, cert_error_reason))
+    } else if err.is_ssl_issuer_error() {
+        Some(TlsErrorReason::UntrustedCa)
+    } else if err.is_ssl_cipher()
+        || err.is_ssl_engine_initfailed()
+        || err.is_ssl_engine_notfound()
+        || err.is_ssl_engine_setfailed()
+    {
+        Some(TlsErrorReason::Channel)
+    } else {
+        None
+    }
+}
+
+/// Find out why the server certificate was rejected from the OpenSSL
+/// `X509_V_ERR_*` code of its verification.
+fn cert_error_reason(verify_result: std::os::raw::c_long) -> TlsErrorReason {
+    match verify_result {
+        // curl leaves it unset when the chain is trusted but it rejects the
+        // host name by itself
+        1 => TlsErrorReason::CertNameInvalid,
+        // X509_V_ERR_HOSTNAME_MISMATCH
+        62 => TlsErrorReason::CertNameInvalid,
+        // X509_V_ERR_CERT_NOT_YET_VALID, X509_V_ERR_CERT_HAS_EXPIRED
+        9 | 10 => TlsErrorReason::CertExpired,
+        // X509_V_ERR_CERT_REVOKED
+        23 => TlsErrorReason::CertRevoked,
+        // X509_V_ERR_UNABLE_TO_GET_CRL
+        3 => TlsErrorReason::CertRevocationCheckFailed,
+        // X509_V_ERR_INVALID_PURPOSE
+        26 => TlsErrorReason::CertWrongUsage,
+        // X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT, the self signed ones,
+        // X509_V_ERR_UNABLE_TO_GET_ISSUER_CERT_LOCALLY,
+        // X509_V_ERR_UNABLE_TO_VERIFY_LEAF_SIGNATURE, X509_V_ERR_INVALID_CA
+        // and X509_V_ERR_CERT_UNTRUSTED
+        2 | 18 | 19 | 20 | 21 | 24 | 27 => TlsErrorReason::UntrustedCa,
+        _ => TlsErrorReason::InvalidCert,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::prelude::*;
+
+    /// Serve `ok` to every request after a while, keeping track of the most
+    /// requests handled at the same time.
+    fn counting_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let server_peak = peak.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let (active, peak) = (active.clone(), server_peak.clone());
+                std::thread::spawn(move || {
+                    let mut head = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return,
+                            Ok(size) => head.extend_from_slice(&buf[..size]),
+                        }
+                    }
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(50));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    let _ = stream.write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    );
+                });
+            }
+        });
+        (url, peak)
+    }
+
+    /// Answer every request with its head as the body, and a cookie set
+    /// twice.
+    fn echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut head = Vec::new();
+                let mut buf = [0; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => head.push(buf[0]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head).into_owned();
+                let body_size = head
+                    .lines()
+                    .filter_map(|line| line.split_once(": "))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let _ = stream.read_exact(&mut vec![0; body_size]);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    head.len(),
+                    head
+                );
+            }
+        });
+        url
+    }
+
+    /// Answer every request with the same `response`.
+    fn canned_server(response: &'static str) -> String {
+        slow_server(Duration::ZERO, response)
+    }
+
+    /// Answer every request with the same `response` after `delay`.
+    fn slow_server(delay: Duration, response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut head = Vec::new();
+                let mut buf = [0; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => head.push(buf[0]),
+                    }
+                }
+                std::thread::sleep(delay);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    fn request_head(req: impl std::future::Future<Output = crate::DynResult<Response>>) -> String {
+        futures_lite::future::block_on(async { req.await.unwrap().recv_string().await.unwrap() })
+            .to_lowercase()
+    }
+
+    #[test]
+    fn post_sends_only_the_requested_headers() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(client.post(&url).unwrap());
+        assert!(head.contains("content-length: 0\r\n"));
+        assert!(!head.contains("content-type"));
+        let head = request_head(client.post(&url).unwrap().body_string("hello".into()));
+        assert!(head.contains("content-length: 5\r\n"));
+        // isahc always sends a form content type with a body
+        #[cfg(not(feature = "isahc"))]
+        assert!(!head.contains("content-type"));
+        assert!(!head.contains("expect"));
+        let head = request_head(
+            client
+                .post(&url)
+                .unwrap()
+                .header("Content-Type", "text/plain")
+                .body_string("hello".into()),
+        );
+        assert!(head.contains("content-type: text/plain\r\n"));
+    }
+
+    #[test]
+    fn empty_bodies_send_content_length() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(client.post(&url).unwrap().body_empty());
+        assert!(head.contains("content-length: 0\r\n"), "{head}");
+        assert!(!head.contains("transfer-encoding"), "{head}");
+        let head = request_head(client.get(&url).unwrap());
+        assert!(!head.contains("content-length"), "{head}");
+        let head = request_head(client.get(&url).unwrap().body_empty());
+        assert!(head.contains("content-length: 0\r\n"), "{head}");
+        let head = request_head(client.put(&url).unwrap().body_empty());
+        assert!(head.contains("content-length: 0\r\n"), "{head}");
+    }
+
+    #[test]
+    fn replaced_headers_are_sent_once() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(
+            client
+                .post(&url)
+                .unwrap()
+                .replace_header("Content-Type", "text/plain")
+                .replace_header("Content-Type", "application/json")
+                .body_string("{}".into()),
+        );
+        assert_eq!(head.matches("content-type").count(), 1, "{head}");
+        assert!(head.contains("content-type: application/json\r\n"));
+
+        // Every value added before is replaced
+        let head = request_head(
+            client
+                .get(&url)
+                .unwrap()
+                .header("X-Value", "1")
+                .header("X-Value", "2")
+                .replace_header("X-Value", "3"),
+        );
+        assert_eq!(head.matches("x-value").count(), 1, "{head}");
+        assert!(head.contains("x-value: 3\r\n"));
+    }
+
+    #[test]
+    fn headers_given_twice_are_sent_twice() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(
+            client
+                .get(&url)
+                .unwrap()
+                .headers(vec![("X-A", "1"), ("X-A", "2")]),
+        );
+        assert!(head.contains("x-a: 1\r\n"), "{head}");
+        assert!(head.contains("x-a: 2\r\n"), "{head}");
+    }
+
+    #[test]
+    fn headers_are_read_back() {
+        let client = crate::get_client_builder().build().unwrap();
+        let req = client
+            .get("http://127.0.0.1/")
+            .unwrap()
+            .header("X-A", "1")
+            .header("x-a", "2");
+        assert_eq!(req.get_header("X-A").as_deref(), Some("1, 2"));
+        assert_eq!(req.get_header("X-B"), None);
+        let req = req.replace_header("X-A", "3");
+        assert_eq!(req.get_header("x-a").as_deref(), Some("3"));
+        let req = req.remove_header("X-A");
+        assert_eq!(req.get_header("X-A"), None);
+    }
+
+    #[test]
+    fn removed_headers_are_not_sent() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(client.get(&url).unwrap());
+        assert!(head.contains("accept: */*\r\n"), "{head}");
+        let head = request_head(
+            client
+                .get(&url)
+                .unwrap()
+                .header("X-A", "1")
+                .remove_header("X-A")
+                .remove_header("Accept"),
+        );
+        assert!(!head.contains("x-a"), "{head}");
+        assert!(!head.contains("\r\naccept:"), "{head}");
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn requests_from_http_match_built_requests() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let req = http::Request::put(&url)
+            .header("X-A", "1")
+            .header("Content-Type", "text/plain")
+            .header("Content-Length", "5")
+            .body(futures_lite::io::Cursor::new("hello"))
+            .unwrap();
+        let head = request_head(client.request_from_http(req).unwrap());
+        let expected = request_head(
+            client
+                .put(&url)
+                .unwrap()
+                .header("X-A", "1")
+                .header("Content-Type", "text/plain")
+                .body_string("hello".into()),
+        );
+        assert_eq!(head, expected);
+
+        // A body without Content-Length is never dropped
+        let req = http::Request::get(&url)
+            .body(futures_lite::io::Cursor::new("hello"))
+            .unwrap();
+        let head = request_head(client.request_from_http(req).unwrap());
+        assert!(head.contains("transfer-encoding: chunked\r\n"), "{head}");
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_bodies() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let compressed_size = crate::compress::gzip(b"hello").unwrap().len();
+        let head = request_head(
+            client
+                .post(&url)
+                .unwrap()
+                .gzip_body()
+                .body_string("hello".into()),
+        );
+        assert_eq!(head.matches("content-encoding: gzip\r\n").count(), 1);
+        assert!(head.contains(&format!("content-length: {}\r\n", compressed_size)));
+        // Still a gzip stream without any data
+        let head = request_head(client.post(&url).unwrap().gzip_body());
+        assert_eq!(head.matches("content-encoding: gzip\r\n").count(), 1);
+        assert!(head.contains("content-length: 20\r\n"));
+    }
+
+    /// A writer that isn't ready on every other poll, taking a few bytes at a
+    /// time otherwise.
+    #[derive(Default)]
+    struct SlowWriter {
+        data: Vec<u8>,
+        ready: bool,
+        flushed: bool,
+    }
+
+    impl futures_lite::AsyncWrite for SlowWriter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            let size = buf.len().min(3);
+            self.data.extend_from_slice(&buf[..size]);
+            std::task::Poll::Ready(Ok(size))
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.flushed = true;
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    #[test]
+    fn copy_to_slow_writers() {
+        let url = canned_server(
+            "HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nhello world",
+        );
+        let client = crate::get_client_builder().build().unwrap();
+        let mut writer = SlowWriter::default();
+        let size = futures_lite::future::block_on(async {
+            let res = client.get(&url).unwrap().await.unwrap();
+            res.copy_to(&mut writer).await
+        })
+        .unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(writer.data, b"hello world");
+        assert!(writer.flushed);
+    }
+
+    #[test]
+    fn buffered_lines() {
+        let url = canned_server(
+            "HTTP/1.1 200 OK\r\nContent-Length: 19\r\nConnection: close\r\n\r\n\
+             first\nsecond\r\n\nlast",
+        );
+        let client = crate::get_client_builder().build().unwrap();
+        let lines = futures_lite::future::block_on(async {
+            let res = client.get(&url).unwrap().await.unwrap();
+            let lines = futures_lite::AsyncBufReadExt::lines(res.buffered());
+            futures_lite::StreamExt::try_collect::<_, _, Vec<_>>(lines).await
+        })
+        .unwrap();
+        assert_eq!(lines, ["first", "second", "", "last"]);
+    }
+
+    #[test]
+    fn repeated_headers_are_joined() {
+        let url = canned_server(
+            "HTTP/1.1 200 OK\r\nVary: Accept\r\nSet-Cookie: a=1; Path=/\r\n\
+             vary: Origin\r\nSet-Cookie: b=2\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+        );
+        let client = crate::get_client_builder().build().unwrap();
+        let res = futures_lite::future::block_on(client.get(&url).unwrap()).unwrap();
+        assert_eq!(res.headers()["vary"], "Accept, Origin");
+        // Cookies can't be joined
+        assert_eq!(res.headers()["set-cookie"], "a=1; Path=/");
+        let body = futures_lite::future::block_on(res.recv()).unwrap();
+        let cookies = body
+            .headers_iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Set-Cookie"))
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        assert_eq!(cookies, ["a=1; Path=/", "b=2"]);
+    }
+
+    // isahc lets curl send the request again after the rejection
+    #[cfg(not(feature = "isahc"))]
+    #[test]
+    fn rejected_expectation() {
+        let url = canned_server(
+            "HTTP/1.1 417 Expectation Failed\r\nContent-Length: 5\r\nConnection: close\r\n\r\nnope!",
+        );
+        let client = crate::get_client_builder().build().unwrap();
+        let res = futures_lite::future::block_on(async {
+            let req = client.post(&url).unwrap().expect_continue();
+            req.body_string("a large upload".into())
+                .await
+                .unwrap()
+                .recv()
+                .await
+        })
+        .unwrap();
+        assert_eq!(res.status_code(), 417);
+        assert!(res.data().is_empty());
+    }
+
+    #[test]
+    fn trailers() {
+        let url = canned_server(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Grpc-Status\r\n\
+             Connection: close\r\n\r\n2\r\nok\r\n0\r\nGrpc-Status: 0\r\n\r\n",
+        );
+        let client = crate::get_client_builder().build().unwrap();
+        let trailers = futures_lite::future::block_on(async {
+            client.get(&url).unwrap().await.unwrap().trailers().await
+        })
+        .unwrap();
+        assert_eq!(trailers.len(), 1);
+        assert_eq!(trailers["grpc-status"], "0");
+
+        let body = futures_lite::future::block_on(async {
+            client.get(&url).unwrap().await.unwrap().recv().await
+        })
+        .unwrap();
+        assert_eq!(body.data(), b"ok");
+        assert_eq!(body.trailers()["grpc-status"], "0");
+    }
+
+    #[test]
+    fn slow_request_without_timeout() {
+        let url = slow_server(
+            Duration::from_millis(600),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+        );
+        let client = crate::get_client_builder()
+            .default_timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let err = futures_lite::future::block_on(client.get(&url).unwrap()).err();
+        let err = err.expect("the default timeout has expired");
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>()
+                .map(std::io::Error::kind),
+            Some(std::io::ErrorKind::TimedOut),
+            "{err}"
+        );
+
+        let body = futures_lite::future::block_on(async {
+            client
+                .get(&url)
+                .unwrap()
+                .no_timeout()
+                .await?
+                .recv_string()
+                .await?;
+            crate::DynResult::Ok(())
+        });
+        assert!(body.is_ok(), "{:?}", body.err());
+    }
+
+    #[test]
+    fn truncated_bodies() {
+        let url =
+            canned_server("HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\nabcd");
+        let client = crate::get_client_builder().build().unwrap();
+        let body = futures_lite::future::block_on(async {
+            client.get(&url).unwrap().await.unwrap().recv_bytes().await
+        });
+        assert_eq!(body.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let client = crate::get_client_builder()
+            .allow_truncated_body()
+            .build()
+            .unwrap();
+        let body = futures_lite::future::block_on(async {
+            client.get(&url).unwrap().await.unwrap().recv_bytes().await
+        });
+        assert_eq!(body.unwrap(), b"abcd");
+    }
+
+    #[test]
+    fn body_size_limits() {
+        let client = crate::get_client_builder().build().unwrap();
+        for response in [
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+            // The size is only known once the body is read
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n\
+             3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n",
+        ] {
+            let url = canned_server(response);
+            futures_lite::future::block_on(async {
+                let res = client.get(&url).unwrap().await.unwrap();
+                let data = res.recv_bytes_with_limit(5).await.unwrap();
+                assert_eq!(data, b"hello");
+                let res = client.get(&url).unwrap().await.unwrap();
+                let err = res.recv_bytes_with_limit(4).await.unwrap_err();
+                assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{response}");
+            });
+        }
+    }
+
+    #[test]
+    fn injected_headers_are_rejected() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        for req in [
+            client
+                .get(&url)
+                .unwrap()
+                .header("X-A", "1\r\nInjected: yes"),
+            client
+                .get(&url)
+                .unwrap()
+                .replace_header("X-A\r\nInjected", "yes"),
+        ] {
+            let Err(err) = futures_lite::future::block_on(req) else {
+                panic!("the header has been sent");
+            };
+            assert!(
+                matches!(
+                    err.downcast_ref::<crate::Error>(),
+                    Some(crate::Error::InvalidHeader { .. })
+                ),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn range_headers() {
+        let url = echo_server();
+        let client = crate::get_client_builder().build().unwrap();
+        let head = request_head(client.get(&url).unwrap().range(0, Some(499)));
+        assert!(head.contains("range: bytes=0-499\r\n"));
+        // The last range replaces the previous one
+        let head = request_head(client.get(&url).unwrap().range(0, Some(1)).range(500, None));
+        assert!(head.contains("range: bytes=500-\r\n"));
+        assert!(!head.contains("bytes=0-1"));
+        let head = request_head(client.get(&url).unwrap().range_suffix(100));
+        assert!(head.contains("range: bytes=-100\r\n"));
+    }
+
+    #[test]
+    fn host_uses_count_requests_in_flight() {
+        let uses = HostUses::default();
+        let (first, others) = uses.start("http://example.com/a");
+        assert_eq!(others, 0);
+        let (second, others) = uses.start("http://user@example.com/b?c");
+        assert_eq!(others, 1);
+        let (_, others) = uses.start("http://example.com:8080/");
+        assert_eq!(others, 0);
+        drop((first, second));
+        let (_, others) = uses.start("http://example.com/");
+        assert_eq!(others, 0);
+        // The hosts without a request in flight are forgotten
+        for i in 0..100 {
+            uses.start(&format!("http://host{}.example/", i));
+        }
+        assert!(uses.0.lock().unwrap().len() <= 1);
+    }
+
+    #[test]
+    fn max_concurrent_requests() {
+        let (url, peak) = counting_server();
+        let client = crate::get_client_builder()
+            .max_concurrent(2)
+            .build()
+            .unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (client, url) = (client.clone(), url.clone());
+                std::thread::spawn(move || {
+                    let res = futures_lite::future::block_on(client.get(&url).unwrap()).unwrap();
+                    futures_lite::future::block_on(res.recv_string()).unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), "ok");
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn empty_bodies() {
+        let client = crate::get_client_builder().build().unwrap();
+        for response in [
+            "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ] {
+            let url = canned_server(response);
+            futures_lite::future::block_on(async {
+                let mut res = client.get(&url).unwrap().await.unwrap();
+                let mut buf = [0; 16];
+                let size = futures_lite::AsyncReadExt::read(&mut res, &mut buf).await;
+                assert_eq!(size.unwrap(), 0, "{response}");
+                let size = futures_lite::AsyncReadExt::read(&mut res, &mut buf).await;
+                assert_eq!(size.unwrap(), 0, "{response}");
+
+                let body = client.get(&url).unwrap().await.unwrap().recv().await;
+                let body = body.unwrap();
+                assert!(body.data().is_empty(), "{response}");
+                assert!(body.is_empty(), "{response}");
+            });
+        }
+        let url =
+            canned_server("HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+        let body = futures_lite::future::block_on(async {
+            client.get(&url).unwrap().await.unwrap().recv().await
+        });
+        assert!(!body.unwrap().is_empty());
+    }
+
+    #[test]
+    fn tls_error_reason_from_curl_codes() {
+        let reason =
+            |code, verify_result| curl_tls_error_reason(&curl::Error::new(code), verify_result);
+        assert_eq!(
+            reason(curl_sys::CURLE_PEER_FAILED_VERIFICATION, Some(10)),
+            Some(TlsErrorReason::CertExpired)
+        );
+        assert_eq!(
+            reason(curl_sys::CURLE_PEER_FAILED_VERIFICATION, Some(18)),
+            Some(TlsErrorReason::UntrustedCa)
+        );
+        assert_eq!(
+            reason(curl_sys::CURLE_PEER_FAILED_VERIFICATION, Some(1)),
+            Some(TlsErrorReason::CertNameInvalid)
+        );
+        assert_eq!(
+            reason(curl_sys::CURLE_PEER_FAILED_VERIFICATION, None),
+            Some(TlsErrorReason::InvalidCert)
+        );
+        assert_eq!(
+            reason(curl_sys::CURLE_SSL_PINNEDPUBKEYNOTMATCH, None),
+            Some(TlsErrorReason::PinnedKeyMismatch)
+        );
+        assert_eq!(reason(curl_sys::CURLE_COULDNT_CONNECT, None), None);
     }
 }
@@ -1,15 +1,35 @@
 use std::{
-    collections::HashMap,
+    net::IpAddr,
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
-use futures_lite::{AsyncRead, Future, FutureExt};
-use isahc::{AsyncBody, ResponseFuture};
+use futures_lite::{AsyncRead, Future};
 
-use crate::{prelude::CommonRequest, DynResult};
+use crate::{
+    cancel::{cancelled_error, CancelState},
+    concurrency::{Permit, PermitWait},
+    error::{tls_error, validate_header},
+    prelude::CommonRequest,
+    proxy::EnvProxy,
+    rate_limit::RateLimitWait,
+    response::{expected_body_size, join_header_values},
+    retry::{is_idempotent, ReplayBody, RetryPolicy},
+    timer::{connect_retry_delay, Deadline, Delay},
+    trace::RequestSpan,
+    CancelHandle, Client, DynError, DynResult, Method, TaggedError, Timing,
+};
 
-use super::{response::CURLResponse, SHARED};
+use super::{
+    response::CURLResponse,
+    transport::{self, Transfer, TransferError},
+    BoxedReader, RequestBody, TransferOptions,
+};
 
 #[derive(Clone, Copy)]
 enum RequestState {
@@ -17,108 +37,361 @@ enum RequestState {
     Recv,
 }
 
+/// Keeps the body shared with the request handed over to the transport, so it
+/// can be sent again when connecting fails before anything of it has been read.
+#[derive(Clone)]
+struct RetainedBody {
+    inner: Arc<Mutex<BoxedReader>>,
+    touched: Arc<AtomicBool>,
+}
+
+impl RetainedBody {
+    fn new(body: BoxedReader) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(body)),
+            touched: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn is_touched(&self) -> bool {
+        self.touched.load(Ordering::Acquire)
+    }
+}
+
+impl AsyncRead for RetainedBody {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.touched.store(true, Ordering::Release);
+        Pin::new(&mut **self.inner.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
 pub struct CURLRequest {
     state: RequestState,
-    req_builder: Option<isahc::http::request::Builder>,
-    body: Option<Box<dyn AsyncRead + Unpin + Send + Sync + 'static>>,
-    res: Option<ResponseFuture<'static>>,
+    method: Method,
+    url: String,
+    headers: Vec<(String, String)>,
+    /// Headers the transport mustn't send, even the ones it adds by itself.
+    removed_headers: Vec<String>,
+    timeout: Option<Duration>,
+    http1_only: bool,
+    local_address: Option<IpAddr>,
+    ip_version: crate::IpVersion,
+    env_proxy: Option<Arc<EnvProxy>>,
+    body: Option<BoxedReader>,
+    body_size: Option<u64>,
+    retained_body: Option<RetainedBody>,
+    transfer: Option<Transfer>,
+    sent_at: Instant,
+    cancel: Arc<CancelState>,
+    deadline: Option<Deadline>,
+    rate_limit: Option<RateLimitWait>,
+    concurrency: Option<PermitWait>,
+    permit: Option<Permit>,
+    /// Counts the request in flight to its host while it's alive.
+    host_use: Option<Arc<()>>,
+    forbid_reuse: bool,
+    pinned_public_key: Option<[u8; 32]>,
+    min_tls_version: Option<crate::TlsVersion>,
+    decompress: bool,
+    connect_retries: u32,
+    connect_attempts: u32,
+    retry_delay: Option<Delay>,
+    retry: Option<Arc<RetryPolicy>>,
+    retry_attempts: u32,
+    replay_body: Option<ReplayBody>,
+    streamed_body: bool,
+    allow_truncated_body: bool,
+    max_response_size: Option<u64>,
+    proxy_credentials: Option<Arc<(String, String)>>,
+    digest_credentials: Option<(String, String)>,
+    tag: Option<String>,
+    span: RequestSpan,
+    invalid_header: Option<crate::Error>,
+    #[cfg(feature = "gzip")]
+    compress_threshold: Option<usize>,
+    #[cfg(feature = "gzip")]
+    gzip_body: bool,
 }
 
 impl CURLRequest {
-    pub(crate) fn new(req_builder: isahc::http::request::Builder) -> Self {
+    pub(crate) fn new(method: Method, url: &str, client: &Client) -> Self {
+        let (host_use, forbid_reuse) = match client.pool_max_idle_per_host {
+            Some(max_idle) => {
+                let (host_use, others) = client.host_uses.start(url);
+                (Some(host_use), others >= max_idle)
+            }
+            None => (None, false),
+        };
         Self {
             state: RequestState::Building,
-            req_builder: Some(req_builder),
+            method,
+            url: url.to_owned(),
+            headers: Vec::new(),
+            removed_headers: Vec::new(),
+            timeout: client.timeout,
+            http1_only: client.http1_only,
+            local_address: client.local_address,
+            ip_version: client.ip_version,
+            env_proxy: client.env_proxy.clone(),
             body: None,
-            res: None,
+            body_size: None,
+            retained_body: None,
+            transfer: None,
+            sent_at: Instant::now(),
+            cancel: client.requests.register(),
+            deadline: None,
+            rate_limit: client.rate_limiter.clone().map(RateLimitWait::new),
+            concurrency: client.concurrency_limiter.clone().map(PermitWait::new),
+            permit: None,
+            host_use,
+            forbid_reuse,
+            pinned_public_key: client.pinned_public_key,
+            min_tls_version: client.min_tls_version,
+            decompress: client.auto_decompress,
+            connect_retries: client.connect_retries,
+            connect_attempts: 0,
+            retry_delay: None,
+            retry: client.retry.clone(),
+            retry_attempts: 0,
+            replay_body: None,
+            streamed_body: false,
+            allow_truncated_body: client.allow_truncated_body,
+            max_response_size: client.max_response_size,
+            proxy_credentials: client.proxy_credentials.clone(),
+            digest_credentials: None,
+            tag: None,
+            span: RequestSpan::default(),
+            invalid_header: None,
+            #[cfg(feature = "gzip")]
+            compress_threshold: client.compress_threshold,
+            #[cfg(feature = "gzip")]
+            gzip_body: false,
+        }
+    }
+
+    fn take_body(&mut self) -> RequestBody {
+        if let Some(body) = &self.replay_body {
+            return RequestBody::Sized(Box::new(body.reader()), body.len() as u64);
+        }
+        // Only keep the body around when it may be sent again
+        if self.connect_retries > 0 && self.retained_body.is_none() {
+            self.retained_body = self.body.take().map(RetainedBody::new);
+        }
+        let body = match &self.retained_body {
+            Some(body) => Some(Box::new(body.clone()) as BoxedReader),
+            None => self.body.take(),
+        };
+        match (body, self.body_size) {
+            (Some(body), Some(size)) => RequestBody::Sized(body, size),
+            (Some(body), None) => RequestBody::Chunked(body),
+            (None, _) => RequestBody::Empty,
+        }
+    }
+
+    fn transfer_options(&self) -> TransferOptions<'_> {
+        TransferOptions {
+            method: self.method,
+            url: &self.url,
+            headers: &self.headers,
+            removed_headers: &self.removed_headers,
+            timeout: self.timeout,
+            http1_only: self.http1_only,
+            local_address: self.local_address,
+            ip_version: self.ip_version,
+            env_proxy: self.env_proxy.as_deref(),
+            proxy_credentials: self.proxy_credentials.as_deref(),
+            digest_credentials: self.digest_credentials.as_ref(),
+            forbid_reuse: self.forbid_reuse,
+            pinned_public_key: self.pinned_public_key,
+            min_tls_version: self.min_tls_version,
+            decompress: self.decompress,
+        }
+    }
+
+    /// Turn the error into a [`DynError`], tagged if the request has a tag.
+    fn fail<E: std::error::Error + Send + Sync + 'static>(&self, err: E) -> DynError {
+        self.span.failed(&err);
+        match &self.tag {
+            Some(tag) => TaggedError::new(tag.as_str(), err).into(),
+            None => err.into(),
         }
     }
+
+    /// The retry policy if the request can be sent again, which needs it to be
+    /// idempotent and its body to be replayable.
+    fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_deref().filter(|policy| {
+            policy.has_attempts_left(self.retry_attempts + 1)
+                && is_idempotent(self.method)
+                && !self.streamed_body
+        })
+    }
+
+    /// Send the request again after `delay`.
+    fn retry_after(&mut self, delay: Duration, cx: &mut Context<'_>) {
+        self.retry_attempts += 1;
+        self.transfer = None;
+        self.state = RequestState::Building;
+        self.retry_delay = Some(Delay::new(delay));
+        cx.waker().wake_by_ref();
+    }
+
+    /// Whether the failure happened while connecting and the request can be
+    /// safely sent again.
+    fn should_retry_connect(&self, err: &TransferError) -> bool {
+        transport::is_connect_failure(err)
+            && self.connect_attempts < self.connect_retries
+            && !self
+                .retained_body
+                .as_ref()
+                .map(RetainedBody::is_touched)
+                .unwrap_or(false)
+    }
 }
 
 impl Future for CURLRequest {
     type Output = DynResult<CURLResponse>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.span.start(this.method, &this.url, this.tag.as_deref());
+        if let Some(err) = self.invalid_header.take() {
+            return Poll::Ready(Err(self.fail(err)));
+        }
+        if self.cancel.is_cancelled() {
+            // Dropping the response future aborts the transfer
+            self.transfer = None;
+            return Poll::Ready(Err(self.fail(cancelled_error())));
+        }
+        self.cancel.register(cx.waker());
+        if let Some(Err(err)) = self.deadline.as_mut().map(|x| x.check(cx)) {
+            self.transfer = None;
+            return Poll::Ready(Err(self.fail(err)));
+        }
+        if let Some(retry_delay) = &mut self.retry_delay {
+            if Pin::new(retry_delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.retry_delay = None;
+        }
         match self.state {
             RequestState::Building => {
-                if let Some(req_builder) = self.req_builder.take() {
-                    let body = self
-                        .body
-                        .take()
-                        .unwrap_or_else(|| Box::new(futures_lite::io::empty()));
-                    match req_builder.body(AsyncBody::from_reader(body)) {
-                        Ok(req) => {
-                            let res = SHARED.send_async(req);
-                            self.res = Some(res);
-                            self.state = RequestState::Recv;
-                            cx.waker().wake_by_ref();
-                            Poll::Pending
+                if self.permit.is_none() {
+                    if let Some(concurrency) = &mut self.concurrency {
+                        match concurrency.poll_acquire(cx) {
+                            Poll::Ready(permit) => self.permit = Some(permit),
+                            Poll::Pending => return Poll::Pending,
                         }
-                        Err(_) => Poll::Ready(Err({
-                            #[cfg(not(feature = "anyhow"))]
-                            {
-                                Box::from("isahc error")
-                            }
-                            #[cfg(feature = "anyhow")]
-                            {
-                                anyhow::anyhow!("isahc error")
-                            }
-                        })),
                     }
-                } else {
-                    Poll::Ready(Err({
-                        #[cfg(not(feature = "anyhow"))]
-                        {
-                            Box::from("already polled")
-                        }
-                        #[cfg(feature = "anyhow")]
-                        {
-                            anyhow::anyhow!("already polled")
-                        }
-                    }))
+                }
+                if let Some(rate_limit) = &mut self.rate_limit {
+                    if rate_limit.poll_acquire(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                }
+                let body = self.take_body();
+                self.sent_at = Instant::now();
+                match transport::send(&self.transfer_options(), body) {
+                    Ok(transfer) => {
+                        self.transfer = Some(transfer);
+                        self.state = RequestState::Recv;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                    Err(err) => Poll::Ready(Err(self.fail(err))),
                 }
             }
             RequestState::Recv => {
-                if let Some(res) = &mut self.as_mut().res {
-                    match res.poll(cx) {
+                if let Some(transfer) = &mut self.as_mut().transfer {
+                    match Pin::new(transfer).poll(cx) {
                         Poll::Ready(Ok(res)) => {
-                            let code = res.status().as_u16();
-                            let mut headers = HashMap::with_capacity(res.headers().len());
-                            for (name, value) in res.headers().iter() {
-                                headers.insert(
-                                    name.as_str().to_string(),
-                                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
-                                );
+                            let code = res.code;
+                            if let Some(rate_limit) = &self.rate_limit {
+                                let retry_after =
+                                    res.headers.get("retry-after").map(String::as_str);
+                                rate_limit.limiter().handle_response(code, retry_after);
+                            }
+                            let retry_delay = self
+                                .retry_policy()
+                                .filter(|policy| policy.should_retry_status(code))
+                                .map(|policy| {
+                                    let retry_after =
+                                        res.headers.get("retry-after").map(String::as_str);
+                                    policy.delay(self.retry_attempts + 1, retry_after)
+                                });
+                            if let Some(delay) = retry_delay {
+                                self.retry_after(delay, cx);
+                                return Poll::Pending;
                             }
+                            let expected_size = if self.allow_truncated_body
+                                || res.body_discarded
+                                || matches!(self.method, Method::HEAD)
+                            {
+                                None
+                            } else {
+                                expected_body_size(code, &res.headers)
+                            };
+                            let timing = Timing::new(self.sent_at);
+                            self.span.response(code, &timing);
                             Poll::Ready(Ok(CURLResponse {
-                                res: res.into_body(),
+                                res: res.body,
                                 code,
-                                headers,
+                                version: res.version,
+                                reason: res.reason,
+                                headers: res.headers,
+                                ordered_headers: res.ordered_headers,
+                                raw_headers: res.raw_headers,
+                                tls_info: res.tls_info,
+                                read_size: 0,
+                                expected_size,
+                                allow_truncated_body: self.allow_truncated_body,
+                                max_size: self.max_response_size,
+                                permit: self.permit.take(),
+                                host_use: self.host_use.take(),
+                                cancel: self.cancel.clone(),
+                                deadline: self.deadline.clone(),
+                                sent_at: self.sent_at,
+                                timing,
+                                span: self.span.clone(),
                             }))
                         }
-                        Poll::Ready(Err(_)) => Poll::Ready(Err({
-                            #[cfg(not(feature = "anyhow"))]
-                            {
-                                Box::from("isahc error")
-                            }
-                            #[cfg(feature = "anyhow")]
-                            {
-                                anyhow::anyhow!("isahc error")
+                        Poll::Ready(Err(err)) if self.should_retry_connect(&err) => {
+                            self.connect_attempts += 1;
+                            self.transfer = None;
+                            self.state = RequestState::Building;
+                            self.retry_delay =
+                                Some(Delay::new(connect_retry_delay(self.connect_attempts)));
+                            cx.waker().wake_by_ref();
+                            Poll::Pending
+                        }
+                        Poll::Ready(Err(err)) => {
+                            let retry_delay = self
+                                .retry_policy()
+                                .filter(|policy| {
+                                    policy.should_retry_error(transport::io_error_kind(&err))
+                                })
+                                .map(|policy| policy.delay(self.retry_attempts + 1, None));
+                            if let Some(delay) = retry_delay {
+                                self.retry_after(delay, cx);
+                                return Poll::Pending;
                             }
-                        })),
+                            Poll::Ready(Err(match transport::tls_error_reason(&err) {
+                                Some(reason) => self.fail(tls_error(reason)),
+                                // Keep the original error, which tells what
+                                // has failed like resolving the host name,
+                                // classified like the ones on Windows
+                                None => self
+                                    .fail(std::io::Error::new(transport::io_error_kind(&err), err)),
+                            }))
+                        }
                         Poll::Pending => Poll::Pending,
                     }
                 } else {
-                    Poll::Ready(Err({
-                        #[cfg(not(feature = "anyhow"))]
-                        {
-                            Box::from("already polled")
-                        }
-                        #[cfg(feature = "anyhow")]
-                        {
-                            anyhow::anyhow!("already polled")
-                        }
-                    }))
+                    Poll::Ready(Err(self.fail(std::io::Error::other("already polled"))))
                 }
             }
         }
@@ -129,17 +402,147 @@ impl CommonRequest for CURLRequest {
     fn body(
         mut self,
         new_body: impl AsyncRead + Unpin + Send + Sync + 'static,
-        _body_size: usize,
+        body_size: usize,
     ) -> Self {
+        #[cfg(feature = "gzip")]
+        if self.gzip_body {
+            // Don't compress the compressed body again
+            self.gzip_body = false;
+            let mut req = crate::compress::set_gzip_body(self, new_body);
+            req.gzip_body = true;
+            return req;
+        }
+        self.body_size = Some(body_size as u64);
+        self.replay_body = None;
+        self.streamed_body = false;
+        // Keep buffered bodies to send them again when retrying
+        let new_body = match self.retry {
+            Some(_) => match ReplayBody::from_body(new_body) {
+                Ok(body) => {
+                    self.body = None;
+                    self.replay_body = Some(body);
+                    return self;
+                }
+                Err(body) => body,
+            },
+            None => new_body,
+        };
         self.body = Some(Box::new(new_body));
+        self.streamed_body = true;
         self
     }
 
+    fn body_chunked(mut self, new_body: impl AsyncRead + Unpin + Send + Sync + 'static) -> Self {
+        #[cfg(feature = "gzip")]
+        if self.gzip_body {
+            // Don't compress the compressed body again
+            self.gzip_body = false;
+            let mut req = crate::compress::set_gzip_body(self, new_body);
+            req.gzip_body = true;
+            return req;
+        }
+        self.body = Some(Box::new(new_body));
+        self.body_size = None;
+        self.replay_body = None;
+        self.streamed_body = true;
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    fn gzip_body(mut self) -> Self {
+        if self.gzip_body {
+            return self;
+        }
+        self = self.replace_header("Content-Encoding", "gzip");
+        self.gzip_body = true;
+        // Compress the body provided before
+        if let Some(body) = self.replay_body.take() {
+            let data = body.as_ref().to_vec();
+            let len = data.len();
+            self = self.body(futures_lite::io::Cursor::new(data), len);
+        } else if let Some(body) = self.body.take().filter(|_| self.body_size != Some(0)) {
+            self = self.body_chunked(body);
+        } else {
+            // Even an empty body has to be a gzip stream
+            self = self.body_bytes(Vec::new());
+        }
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    fn body_bytes(self, body: Vec<u8>) -> Self {
+        // The body is compressed by itself already
+        let threshold = self.compress_threshold.filter(|_| !self.gzip_body);
+        crate::compress::body_bytes_compressed(self, threshold, body)
+    }
+
     fn header(mut self, header: &str, value: &str) -> Self {
-        let req_builder = self.req_builder.take();
-        if let Some(req_builder) = req_builder {
-            self.req_builder = Some(req_builder.header(header, value));
+        match validate_header(header, value) {
+            Ok(()) => {
+                self.removed_headers
+                    .retain(|x| !x.eq_ignore_ascii_case(header));
+                self.headers.push((header.to_owned(), value.to_owned()));
+            }
+            Err(err) => {
+                self.invalid_header.get_or_insert(err);
+            }
         }
         self
     }
+
+    fn replace_header(mut self, header: &str, value: &str) -> Self {
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+        self.header(header, value)
+    }
+
+    fn remove_header(mut self, header: &str) -> Self {
+        if let Err(err) = validate_header(header, "") {
+            self.invalid_header.get_or_insert(err);
+            return self;
+        }
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+        if !self
+            .removed_headers
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case(header))
+        {
+            self.removed_headers.push(header.to_owned());
+        }
+        self
+    }
+
+    fn get_header(&self, header: &str) -> Option<String> {
+        join_header_values(&self.headers, header)
+    }
+
+    fn deadline(mut self, at: Instant) -> Self {
+        self.deadline = Some(Deadline::new(at));
+        self
+    }
+
+    fn no_timeout(mut self) -> Self {
+        // curl treats a zero timeout as "never time out"
+        self.timeout = Some(Duration::ZERO);
+        self
+    }
+
+    fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    fn digest_auth(mut self, user: &str, pass: &str) -> Self {
+        self.digest_credentials = Some((user.to_owned(), pass.to_owned()));
+        self
+    }
+
+    fn cancellable(self) -> (Self, CancelHandle) {
+        // The state is also cancelled by the shutdown of the client
+        let handle = CancelHandle {
+            state: self.cancel.clone(),
+        };
+        (self, handle)
+    }
 }
@@ -1,16 +1,40 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, sync::Arc, time::Instant};
 
 use futures_lite::{AsyncRead, AsyncReadExt};
-use isahc::AsyncBody;
 
-use crate::ResponseBody;
+use crate::{
+    cancel::{cancelled_error, CancelState},
+    concurrency::Permit,
+    response::{body_capacity, check_body_limit, truncated_body_error},
+    timer::Deadline,
+    trace::RequestSpan,
+    HttpVersion, ResponseBody, Timing, TlsInfo,
+};
+
+use super::transport::{self, TransferBody};
 
 pin_project_lite::pin_project! {
 pub struct CURLResponse {
     #[pin]
-    pub(crate) res: AsyncBody,
+    pub(crate) res: TransferBody,
     pub(crate) code: u16,
+    pub(crate) version: HttpVersion,
+    pub(crate) reason: Option<Cow<'static, str>>,
     pub(crate) headers: HashMap<String, String>,
+    pub(crate) ordered_headers: Vec<(String, String)>,
+    pub(crate) raw_headers: Vec<(String, Vec<u8>)>,
+    pub(crate) tls_info: Option<TlsInfo>,
+    pub(crate) read_size: u64,
+    pub(crate) expected_size: Option<u64>,
+    pub(crate) allow_truncated_body: bool,
+    pub(crate) max_size: Option<u64>,
+    pub(crate) permit: Option<Permit>,
+    pub(crate) host_use: Option<Arc<()>>,
+    pub(crate) cancel: Arc<CancelState>,
+    pub(crate) deadline: Option<Deadline>,
+    pub(crate) sent_at: Instant,
+    pub(crate) timing: Timing,
+    pub(crate) span: RequestSpan,
 }
 }
 
@@ -20,19 +44,95 @@ impl AsyncRead for CURLResponse {
         cx: &mut std::task::Context<'_>,
         buf: &mut [u8],
     ) -> std::task::Poll<std::io::Result<usize>> {
-        self.project().res.poll_read(cx, buf)
+        let this = self.project();
+        if this.cancel.is_cancelled() {
+            return std::task::Poll::Ready(Err(cancelled_error()));
+        }
+        this.cancel.register(cx.waker());
+        if let Some(deadline) = this.deadline {
+            if let Err(err) = deadline.check(cx) {
+                return std::task::Poll::Ready(Err(err));
+            }
+        }
+        let result = this.res.poll_read(cx, buf);
+        if let std::task::Poll::Ready(Ok(0)) = result {
+            // The transfer has completed, let other requests of the client go
+            *this.permit = None;
+            *this.host_use = None;
+            if this.timing.total().is_none() {
+                this.timing.finish(*this.sent_at);
+                this.span.finished(this.timing);
+            }
+        }
+        match result {
+            std::task::Poll::Ready(Ok(0)) if !buf.is_empty() => match *this.expected_size {
+                Some(expected) if *this.read_size < expected => {
+                    std::task::Poll::Ready(Err(truncated_body_error(*this.read_size, expected)))
+                }
+                _ => std::task::Poll::Ready(Ok(0)),
+            },
+            std::task::Poll::Ready(Ok(size)) => {
+                *this.read_size += size as u64;
+                if let Err(err) =
+                    check_body_limit(*this.read_size, *this.expected_size, *this.max_size)
+                {
+                    return std::task::Poll::Ready(Err(err));
+                }
+                std::task::Poll::Ready(Ok(size))
+            }
+            // curl reports it by itself when the connection closes early
+            std::task::Poll::Ready(Err(err)) if transport::is_partial_transfer(&err) => {
+                if *this.allow_truncated_body {
+                    return std::task::Poll::Ready(Ok(0));
+                }
+                std::task::Poll::Ready(Err(match *this.expected_size {
+                    Some(expected) => truncated_body_error(*this.read_size, expected),
+                    None => std::io::Error::new(std::io::ErrorKind::UnexpectedEof, err),
+                }))
+            }
+            result => result,
+        }
     }
 }
 
 #[cfg_attr(feature = "async_t", async_t::async_trait)]
 impl crate::prelude::CommonResponse for CURLResponse {
+    fn status_code(&self) -> u16 {
+        self.code
+    }
+
+    fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    fn http_version(&self) -> HttpVersion {
+        self.version
+    }
+
+    fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    fn tls_info(&self) -> Option<TlsInfo> {
+        self.tls_info.clone()
+    }
+
+    async fn trailers(mut self) -> std::io::Result<HashMap<String, String>> {
+        futures_lite::io::copy(&mut self, &mut futures_lite::io::sink()).await?;
+        Ok(self.res.trailers())
+    }
+
     async fn recv(mut self) -> std::io::Result<ResponseBody> {
-        let mut data = Vec::with_capacity(1024 * 1024);
+        let mut data = Vec::with_capacity(body_capacity(self.expected_size, 1024 * 1024));
         self.read_to_end(&mut data).await?;
         Ok(ResponseBody {
             data,
             code: self.code,
-            headers: self.headers,
+            reason: self.reason,
+            headers: self.ordered_headers,
+            raw_headers: self.raw_headers,
+            trailers: self.res.trailers(),
+            timing: self.timing,
         })
     }
 }
@@ -1,20 +1,76 @@
 use std::{
     ffi::{c_void, OsString},
     os::windows::ffi::OsStringExt,
+    sync::Arc,
 };
 
 use windows_sys::Win32::{
     Foundation::{GetLastError, ERROR_INSUFFICIENT_BUFFER},
     Networking::WinHttp::*,
+    Security::Cryptography::{
+        CertFreeCertificateContext, CryptEncodeObject, CryptHashCertificate2,
+        BCRYPT_SHA256_ALGORITHM, CERT_CONTEXT, X509_ASN_ENCODING, X509_PUBLIC_KEY_INFO,
+    },
 };
 
 use crate::windows::{
-    err_code::{resolve_io_error, resolve_io_error_from_error_code},
+    err_code::{resolve_io_error, resolve_io_error_from_error_code, resolve_secure_failure_flags},
     WinHTTPCallbackEvent,
 };
+use crate::{error::tls_error, TlsErrorReason};
 
 use super::NetworkContext;
 
+/// Whether the SHA-256 hash of the DER encoded `SubjectPublicKeyInfo` of the
+/// server certificate is `pin`.
+unsafe fn has_pinned_public_key(h_request: *mut c_void, pin: &[u8; 32]) -> bool {
+    let mut cert: *const CERT_CONTEXT = std::ptr::null();
+    let mut size = std::mem::size_of::<*const CERT_CONTEXT>() as u32;
+    let r = WinHttpQueryOption(
+        h_request,
+        WINHTTP_OPTION_SERVER_CERT_CONTEXT,
+        &mut cert as *mut _ as *mut c_void,
+        &mut size,
+    );
+    if r == 0 || cert.is_null() {
+        return false;
+    }
+    let info = &(*(*cert).pCertInfo).SubjectPublicKeyInfo as *const _ as *const c_void;
+    let mut matched = false;
+    let mut encoded_size = 0;
+    if CryptEncodeObject(
+        X509_ASN_ENCODING,
+        X509_PUBLIC_KEY_INFO,
+        info,
+        std::ptr::null_mut(),
+        &mut encoded_size,
+    ) != 0
+    {
+        let mut encoded = vec![0u8; encoded_size as usize];
+        let mut hash = [0u8; 32];
+        let mut hash_size = hash.len() as u32;
+        matched = CryptEncodeObject(
+            X509_ASN_ENCODING,
+            X509_PUBLIC_KEY_INFO,
+            info,
+            encoded.as_mut_ptr(),
+            &mut encoded_size,
+        ) != 0
+            && CryptHashCertificate2(
+                BCRYPT_SHA256_ALGORITHM,
+                0,
+                std::ptr::null(),
+                encoded.as_ptr(),
+                encoded_size,
+                hash.as_mut_ptr(),
+                &mut hash_size,
+            ) != 0
+            && hash == *pin;
+    }
+    CertFreeCertificateContext(cert);
+    matched
+}
+
 pub unsafe extern "system" fn status_callback(
     h_request: *mut c_void,
     dw_context: usize,
@@ -22,25 +78,21 @@ pub unsafe extern "system" fn status_callback(
     lpv_status_infomation: *mut c_void,
     dw_status_infomation_length: u32,
 ) {
-    let ctx = dw_context as *mut NetworkContext;
+    let ctx = dw_context as *const NetworkContext;
 
-    if let Some(ctx) = ctx.as_mut() {
+    if let Some(ctx) = ctx.as_ref() {
         match dw_internet_status {
             WINHTTP_CALLBACK_STATUS_SENDREQUEST_COMPLETE => {
                 let _ = ctx
                     .callback_sender
                     .send(WinHTTPCallbackEvent::WriteCompleted);
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_WRITE_COMPLETE => {
                 let _ = ctx
                     .callback_sender
                     .send(WinHTTPCallbackEvent::WriteCompleted);
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_HEADERS_AVAILABLE => {
                 let mut header_size = 0;
@@ -60,9 +112,7 @@ pub unsafe extern "system" fn status_callback(
                         let _ = ctx
                             .callback_sender
                             .send(WinHTTPCallbackEvent::Error(resolve_io_error()));
-                        if let Some(waker) = &ctx.waker {
-                            waker.wake_by_ref();
-                        }
+                        ctx.wake();
                         return;
                     }
                 }
@@ -82,9 +132,7 @@ pub unsafe extern "system" fn status_callback(
                     let _ = ctx
                         .callback_sender
                         .send(WinHTTPCallbackEvent::Error(resolve_io_error()));
-                    if let Some(waker) = &ctx.waker {
-                        waker.wake_by_ref();
-                    }
+                    ctx.wake();
                     return;
                 }
 
@@ -97,39 +145,67 @@ pub unsafe extern "system" fn status_callback(
                     .callback_sender
                     .send(WinHTTPCallbackEvent::RawHeadersReceived(header_data));
 
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_RECEIVING_RESPONSE => {
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_RESPONSE_RECEIVED => {
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_CONNECTION_CLOSED => {
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_DATA_AVAILABLE => {
+                // Read anyway if the size is somehow missing
+                let size = (lpv_status_infomation as *const u32)
+                    .as_ref()
+                    .copied()
+                    .unwrap_or(u32::MAX);
                 let _ = ctx
                     .callback_sender
-                    .send(WinHTTPCallbackEvent::DataAvailable);
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                    .send(WinHTTPCallbackEvent::DataAvailable(size));
+                ctx.wake();
             }
             WINHTTP_CALLBACK_STATUS_READ_COMPLETE => {
-                ctx.buf_size = dw_status_infomation_length as usize;
-                ctx.has_completed = ctx.buf_size == 0;
+                ctx.set_buf_size(dw_status_infomation_length as usize);
+                if dw_status_infomation_length == 0 {
+                    ctx.set_completed();
+                }
                 let _ = ctx.callback_sender.send(WinHTTPCallbackEvent::DataWritten);
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
+                ctx.wake();
+            }
+            WINHTTP_CALLBACK_STATUS_CONNECTED_TO_SERVER => {
+                ctx.set_connected_to_server(true);
+                ctx.wake();
+            }
+            WINHTTP_CALLBACK_STATUS_HANDLE_CLOSING => {
+                // This is the last callback of the handle, release the
+                // reference to the context taken when it was registered.
+                drop(Arc::from_raw(ctx as *const NetworkContext));
+            }
+            WINHTTP_CALLBACK_STATUS_SENDING_REQUEST => {
+                // The TLS handshake is done but nothing has been sent yet
+                if let Some(pin) = ctx.pinned_public_key {
+                    if !has_pinned_public_key(h_request, &pin) {
+                        let _ = ctx
+                            .callback_sender
+                            .send(WinHTTPCallbackEvent::Error(tls_error(
+                                TlsErrorReason::PinnedKeyMismatch,
+                            )));
+                        ctx.wake();
+                        // Abort before the request is sent to the server
+                        if let Some(handle) = ctx.request_handle.upgrade() {
+                            handle.close();
+                        }
+                    }
+                }
+            }
+            WINHTTP_CALLBACK_STATUS_SECURE_FAILURE => {
+                // Followed by a request error with the generic
+                // `ERROR_WINHTTP_SECURE_FAILURE`, keep the detail for it
+                if let Some(flags) = (lpv_status_infomation as *const u32).as_ref() {
+                    *ctx.secure_failure.lock().unwrap() = Some(*flags);
                 }
             }
             WINHTTP_CALLBACK_STATUS_REQUEST_ERROR => {
@@ -138,19 +214,20 @@ pub unsafe extern "system" fn status_callback(
                     .unwrap();
 
                 if result.dwError != ERROR_WINHTTP_OPERATION_CANCELLED {
-                    let _ = ctx.callback_sender.send(WinHTTPCallbackEvent::Error(
-                        resolve_io_error_from_error_code(result.dwError as _),
-                    ));
+                    let secure_failure = ctx.secure_failure.lock().unwrap().take();
+                    let err = match secure_failure {
+                        Some(flags) if result.dwError == ERROR_WINHTTP_SECURE_FAILURE => {
+                            resolve_secure_failure_flags(flags)
+                        }
+                        _ => resolve_io_error_from_error_code(result.dwError as _),
+                    };
+                    let _ = ctx.callback_sender.send(WinHTTPCallbackEvent::Error(err));
 
-                    if let Some(waker) = &ctx.waker {
-                        waker.wake_by_ref();
-                    }
+                    ctx.wake();
                 }
             }
             _other => {
-                if let Some(waker) = &ctx.waker {
-                    waker.wake_by_ref();
-                }
+                ctx.wake();
             }
         }
     }
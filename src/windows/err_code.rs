@@ -1,5 +1,7 @@
 use std::io::ErrorKind;
 
+use crate::{error::tls_error, TlsErrorReason};
+
 use windows_sys::Win32::{
     Foundation::{GetLastError, WIN32_ERROR},
     Networking::WinHttp::*,
@@ -7,34 +9,27 @@ use windows_sys::Win32::{
 
 pub fn resolve_io_error_from_error_code(code: WIN32_ERROR) -> std::io::Error {
     match code {
-        ERROR_WINHTTP_AUTODETECTION_FAILED => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_AUTODETECTION_FAILED: 12180",
-        ),
-        ERROR_WINHTTP_AUTO_PROXY_SERVICE_ERROR => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_AUTO_PROXY_SERVICE_ERROR: 12178",
-        ),
-        ERROR_WINHTTP_BAD_AUTO_PROXY_SCRIPT => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_BAD_AUTO_PROXY_SCRIPT: 12166",
-        ),
-        ERROR_WINHTTP_CANNOT_CALL_AFTER_OPEN => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CANNOT_CALL_AFTER_OPEN: 12103",
-        ),
-        ERROR_WINHTTP_CANNOT_CALL_AFTER_SEND => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CANNOT_CALL_AFTER_SEND: 12102",
-        ),
-        ERROR_WINHTTP_CANNOT_CALL_BEFORE_OPEN => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CANNOT_CALL_BEFORE_OPEN: 12100",
-        ),
-        ERROR_WINHTTP_CANNOT_CALL_BEFORE_SEND => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CANNOT_CALL_BEFORE_SEND: 12101",
-        ),
+        ERROR_WINHTTP_AUTODETECTION_FAILED => {
+            std::io::Error::other("ERROR_WINHTTP_AUTODETECTION_FAILED: 12180")
+        }
+        ERROR_WINHTTP_AUTO_PROXY_SERVICE_ERROR => {
+            std::io::Error::other("ERROR_WINHTTP_AUTO_PROXY_SERVICE_ERROR: 12178")
+        }
+        ERROR_WINHTTP_BAD_AUTO_PROXY_SCRIPT => {
+            std::io::Error::other("ERROR_WINHTTP_BAD_AUTO_PROXY_SCRIPT: 12166")
+        }
+        ERROR_WINHTTP_CANNOT_CALL_AFTER_OPEN => {
+            std::io::Error::other("ERROR_WINHTTP_CANNOT_CALL_AFTER_OPEN: 12103")
+        }
+        ERROR_WINHTTP_CANNOT_CALL_AFTER_SEND => {
+            std::io::Error::other("ERROR_WINHTTP_CANNOT_CALL_AFTER_SEND: 12102")
+        }
+        ERROR_WINHTTP_CANNOT_CALL_BEFORE_OPEN => {
+            std::io::Error::other("ERROR_WINHTTP_CANNOT_CALL_BEFORE_OPEN: 12100")
+        }
+        ERROR_WINHTTP_CANNOT_CALL_BEFORE_SEND => {
+            std::io::Error::other("ERROR_WINHTTP_CANNOT_CALL_BEFORE_SEND: 12101")
+        }
         ERROR_WINHTTP_CANNOT_CONNECT => std::io::Error::new(
             ErrorKind::NotConnected,
             "ERROR_WINHTTP_CANNOT_CONNECT: 12029",
@@ -43,41 +38,32 @@ pub fn resolve_io_error_from_error_code(code: WIN32_ERROR) -> std::io::Error {
             ErrorKind::OutOfMemory,
             "ERROR_WINHTTP_CHUNKED_ENCODING_HEADER_SIZE_OVERFLOW: 12183",
         ),
-        ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED: 12044",
-        ),
-        ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED_PROXY => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED_PROXY: 12187",
-        ),
-        ERROR_WINHTTP_CLIENT_CERT_NO_ACCESS_PRIVATE_KEY => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CLIENT_CERT_NO_ACCESS_PRIVATE_KEY: 12186",
-        ),
-        ERROR_WINHTTP_CLIENT_CERT_NO_PRIVATE_KEY => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_CLIENT_CERT_NO_PRIVATE_KEY: 12185",
-        ),
+        ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED => tls_error(TlsErrorReason::ClientCertRequired),
+        ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED_PROXY => {
+            std::io::Error::other("ERROR_WINHTTP_CLIENT_AUTH_CERT_NEEDED_PROXY: 12187")
+        }
+        ERROR_WINHTTP_CLIENT_CERT_NO_ACCESS_PRIVATE_KEY => {
+            std::io::Error::other("ERROR_WINHTTP_CLIENT_CERT_NO_ACCESS_PRIVATE_KEY: 12186")
+        }
+        ERROR_WINHTTP_CLIENT_CERT_NO_PRIVATE_KEY => {
+            std::io::Error::other("ERROR_WINHTTP_CLIENT_CERT_NO_PRIVATE_KEY: 12185")
+        }
         ERROR_WINHTTP_CONNECTION_ERROR => std::io::Error::new(
             ErrorKind::ConnectionAborted,
             "ERROR_WINHTTP_CONNECTION_ERROR: 12030",
         ),
         ERROR_WINHTTP_FEATURE_DISABLED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_FEATURE_DISABLED: 12192")
+            std::io::Error::other("ERROR_WINHTTP_FEATURE_DISABLED: 12192")
+        }
+        ERROR_WINHTTP_GLOBAL_CALLBACK_FAILED => {
+            std::io::Error::other("ERROR_WINHTTP_GLOBAL_CALLBACK_FAILED: 12191")
+        }
+        ERROR_WINHTTP_HEADER_ALREADY_EXISTS => {
+            std::io::Error::other("ERROR_WINHTTP_HEADER_ALREADY_EXISTS: 12155")
+        }
+        ERROR_WINHTTP_HEADER_COUNT_EXCEEDED => {
+            std::io::Error::other("ERROR_WINHTTP_HEADER_COUNT_EXCEEDED: 12181")
         }
-        ERROR_WINHTTP_GLOBAL_CALLBACK_FAILED => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_GLOBAL_CALLBACK_FAILED: 12191",
-        ),
-        ERROR_WINHTTP_HEADER_ALREADY_EXISTS => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_HEADER_ALREADY_EXISTS: 12155",
-        ),
-        ERROR_WINHTTP_HEADER_COUNT_EXCEEDED => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_HEADER_COUNT_EXCEEDED: 12181",
-        ),
         ERROR_WINHTTP_HEADER_NOT_FOUND => {
             std::io::Error::new(ErrorKind::NotFound, "ERROR_WINHTTP_HEADER_NOT_FOUND: 12150")
         }
@@ -85,20 +71,17 @@ pub fn resolve_io_error_from_error_code(code: WIN32_ERROR) -> std::io::Error {
             ErrorKind::OutOfMemory,
             "ERROR_WINHTTP_HEADER_SIZE_OVERFLOW: 12182",
         ),
-        ERROR_WINHTTP_HTTP_PROTOCOL_MISMATCH => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_HTTP_PROTOCOL_MISMATCH: 12190",
-        ),
-        ERROR_WINHTTP_INCORRECT_HANDLE_STATE => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_INCORRECT_HANDLE_STATE: 12019",
-        ),
-        ERROR_WINHTTP_INCORRECT_HANDLE_TYPE => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_INCORRECT_HANDLE_TYPE: 12018",
-        ),
+        ERROR_WINHTTP_HTTP_PROTOCOL_MISMATCH => {
+            std::io::Error::other("ERROR_WINHTTP_HTTP_PROTOCOL_MISMATCH: 12190")
+        }
+        ERROR_WINHTTP_INCORRECT_HANDLE_STATE => {
+            std::io::Error::other("ERROR_WINHTTP_INCORRECT_HANDLE_STATE: 12019")
+        }
+        ERROR_WINHTTP_INCORRECT_HANDLE_TYPE => {
+            std::io::Error::other("ERROR_WINHTTP_INCORRECT_HANDLE_TYPE: 12018")
+        }
         ERROR_WINHTTP_INTERNAL_ERROR => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_INTERNAL_ERROR: 12004")
+            std::io::Error::other("ERROR_WINHTTP_INTERNAL_ERROR: 12004")
         }
         ERROR_WINHTTP_INVALID_HEADER => std::io::Error::new(
             ErrorKind::InvalidData,
@@ -108,110 +91,168 @@ pub fn resolve_io_error_from_error_code(code: WIN32_ERROR) -> std::io::Error {
             ErrorKind::InvalidInput,
             "ERROR_WINHTTP_INVALID_OPTION: 12009",
         ),
-        ERROR_WINHTTP_INVALID_QUERY_REQUEST => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_INVALID_QUERY_REQUEST: 12154",
-        ),
-        ERROR_WINHTTP_INVALID_SERVER_RESPONSE => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_INVALID_SERVER_RESPONSE: 12152",
-        ),
-        ERROR_WINHTTP_INVALID_URL => {
-            std::io::Error::new(ErrorKind::InvalidInput, "ERROR_WINHTTP_INVALID_URL: 12005")
+        ERROR_WINHTTP_INVALID_QUERY_REQUEST => {
+            std::io::Error::other("ERROR_WINHTTP_INVALID_QUERY_REQUEST: 12154")
         }
-        ERROR_WINHTTP_LOGIN_FAILURE => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_LOGIN_FAILURE: 12015")
+        ERROR_WINHTTP_INVALID_SERVER_RESPONSE => {
+            std::io::Error::other("ERROR_WINHTTP_INVALID_SERVER_RESPONSE: 12152")
         }
-        ERROR_WINHTTP_NAME_NOT_RESOLVED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_NAME_NOT_RESOLVED: 12007")
+        ERROR_WINHTTP_INVALID_URL => {
+            std::io::Error::new(ErrorKind::InvalidInput, "ERROR_WINHTTP_INVALID_URL: 12005")
         }
+        ERROR_WINHTTP_LOGIN_FAILURE => std::io::Error::other("ERROR_WINHTTP_LOGIN_FAILURE: 12015"),
+        ERROR_WINHTTP_NAME_NOT_RESOLVED => std::io::Error::new(
+            ErrorKind::NotFound,
+            "ERROR_WINHTTP_NAME_NOT_RESOLVED: 12007",
+        ),
         ERROR_WINHTTP_NOT_INITIALIZED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_NOT_INITIALIZED: 12172")
+            std::io::Error::other("ERROR_WINHTTP_NOT_INITIALIZED: 12172")
         }
         ERROR_WINHTTP_OPERATION_CANCELLED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_OPERATION_CANCELLED: 12017")
+            std::io::Error::other("ERROR_WINHTTP_OPERATION_CANCELLED: 12017")
         }
         ERROR_WINHTTP_OPTION_NOT_SETTABLE => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_OPTION_NOT_SETTABLE: 12011")
+            std::io::Error::other("ERROR_WINHTTP_OPTION_NOT_SETTABLE: 12011")
         }
         ERROR_WINHTTP_OUT_OF_HANDLES => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_OUT_OF_HANDLES: 12001")
+            std::io::Error::other("ERROR_WINHTTP_OUT_OF_HANDLES: 12001")
         }
         ERROR_WINHTTP_REDIRECT_FAILED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_REDIRECT_FAILED: 12156")
+            std::io::Error::other("ERROR_WINHTTP_REDIRECT_FAILED: 12156")
         }
         ERROR_WINHTTP_RESEND_REQUEST => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_RESEND_REQUEST: 12032")
-        }
-        ERROR_WINHTTP_RESERVED_189 => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_RESERVED_189: 12189")
+            std::io::Error::other("ERROR_WINHTTP_RESEND_REQUEST: 12032")
         }
+        ERROR_WINHTTP_RESERVED_189 => std::io::Error::other("ERROR_WINHTTP_RESERVED_189: 12189"),
         ERROR_WINHTTP_RESPONSE_DRAIN_OVERFLOW => std::io::Error::new(
             ErrorKind::OutOfMemory,
             "ERROR_WINHTTP_RESPONSE_DRAIN_OVERFLOW: 12184",
         ),
-        ERROR_WINHTTP_SCRIPT_EXECUTION_ERROR => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SCRIPT_EXECUTION_ERROR: 12177",
-        ),
-        ERROR_WINHTTP_SECURE_CERT_CN_INVALID => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_CERT_CN_INVALID: 12038",
-        ),
-        ERROR_WINHTTP_SECURE_CERT_DATE_INVALID => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_CERT_DATE_INVALID: 12037",
-        ),
-        ERROR_WINHTTP_SECURE_CERT_REVOKED => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_SECURE_CERT_REVOKED: 12170")
+        ERROR_WINHTTP_SCRIPT_EXECUTION_ERROR => {
+            std::io::Error::other("ERROR_WINHTTP_SCRIPT_EXECUTION_ERROR: 12177")
         }
-        ERROR_WINHTTP_SECURE_CERT_REV_FAILED => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_CERT_REV_FAILED: 12057",
-        ),
-        ERROR_WINHTTP_SECURE_CERT_WRONG_USAGE => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_CERT_WRONG_USAGE: 12179",
-        ),
-        ERROR_WINHTTP_SECURE_CHANNEL_ERROR => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_CHANNEL_ERROR: 12157",
-        ),
-        ERROR_WINHTTP_SECURE_FAILURE => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_SECURE_FAILURE: 12175")
-        }
-        ERROR_WINHTTP_SECURE_FAILURE_PROXY => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_SECURE_FAILURE_PROXY: 12188",
-        ),
-        ERROR_WINHTTP_SECURE_INVALID_CA => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_SECURE_INVALID_CA: 12045")
+        ERROR_WINHTTP_SECURE_CERT_CN_INVALID => tls_error(TlsErrorReason::CertNameInvalid),
+        ERROR_WINHTTP_SECURE_CERT_DATE_INVALID => tls_error(TlsErrorReason::CertExpired),
+        ERROR_WINHTTP_SECURE_CERT_REVOKED => tls_error(TlsErrorReason::CertRevoked),
+        ERROR_WINHTTP_SECURE_CERT_REV_FAILED => {
+            tls_error(TlsErrorReason::CertRevocationCheckFailed)
         }
-        ERROR_WINHTTP_SECURE_INVALID_CERT => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_SECURE_INVALID_CERT: 12169")
-        }
-        ERROR_WINHTTP_SHUTDOWN => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_SHUTDOWN: 12012")
+        ERROR_WINHTTP_SECURE_CERT_WRONG_USAGE => tls_error(TlsErrorReason::CertWrongUsage),
+        ERROR_WINHTTP_SECURE_CHANNEL_ERROR => tls_error(TlsErrorReason::Channel),
+        ERROR_WINHTTP_SECURE_FAILURE | ERROR_WINHTTP_SECURE_FAILURE_PROXY => {
+            tls_error(TlsErrorReason::InvalidCert)
         }
+        ERROR_WINHTTP_SECURE_INVALID_CA => tls_error(TlsErrorReason::UntrustedCa),
+        ERROR_WINHTTP_SECURE_INVALID_CERT => tls_error(TlsErrorReason::InvalidCert),
+        ERROR_WINHTTP_SHUTDOWN => std::io::Error::other("ERROR_WINHTTP_SHUTDOWN: 12012"),
         ERROR_WINHTTP_TIMEOUT => {
             std::io::Error::new(ErrorKind::TimedOut, "ERROR_WINHTTP_TIMEOUT: 12002")
         }
-        ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT: 12167",
-        ),
-        ERROR_WINHTTP_UNHANDLED_SCRIPT_TYPE => std::io::Error::new(
-            ErrorKind::Other,
-            "ERROR_WINHTTP_UNHANDLED_SCRIPT_TYPE: 12176",
-        ),
+        ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT => {
+            std::io::Error::other("ERROR_WINHTTP_UNABLE_TO_DOWNLOAD_SCRIPT: 12167")
+        }
+        ERROR_WINHTTP_UNHANDLED_SCRIPT_TYPE => {
+            std::io::Error::other("ERROR_WINHTTP_UNHANDLED_SCRIPT_TYPE: 12176")
+        }
         ERROR_WINHTTP_UNRECOGNIZED_SCHEME => {
-            std::io::Error::new(ErrorKind::Other, "ERROR_WINHTTP_UNRECOGNIZED_SCHEME: 12006")
+            std::io::Error::other("ERROR_WINHTTP_UNRECOGNIZED_SCHEME: 12006")
         }
 
         other => std::io::Error::from_raw_os_error(other as _),
     }
 }
 
+/// Resolve the flags reported by `WINHTTP_CALLBACK_STATUS_SECURE_FAILURE`,
+/// which are more detailed than the following `ERROR_WINHTTP_SECURE_FAILURE`.
+pub fn resolve_secure_failure_flags(flags: u32) -> std::io::Error {
+    let reason = if flags & WINHTTP_CALLBACK_STATUS_FLAG_CERT_REVOKED != 0 {
+        TlsErrorReason::CertRevoked
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_CERT_DATE_INVALID != 0 {
+        TlsErrorReason::CertExpired
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_INVALID_CA != 0 {
+        TlsErrorReason::UntrustedCa
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_CERT_CN_INVALID != 0 {
+        TlsErrorReason::CertNameInvalid
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_CERT_WRONG_USAGE != 0 {
+        TlsErrorReason::CertWrongUsage
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_CERT_REV_FAILED != 0 {
+        TlsErrorReason::CertRevocationCheckFailed
+    } else if flags & WINHTTP_CALLBACK_STATUS_FLAG_SECURITY_CHANNEL_ERROR != 0 {
+        TlsErrorReason::Channel
+    } else {
+        TlsErrorReason::InvalidCert
+    };
+    tls_error(reason)
+}
+
 pub fn resolve_io_error() -> std::io::Error {
     resolve_io_error_from_error_code(unsafe { GetLastError() })
 }
+
+/// Whether the request failed with `ERROR_WINHTTP_CONNECTION_ERROR` on a
+/// kept-alive connection, which the server may have closed since its last
+/// use.
+pub fn is_stale_connection_error(err: &std::io::Error, connected_to_server: bool) -> bool {
+    // Failing to connect or on a new connection isn't about a stale one
+    err.kind() == ErrorKind::ConnectionAborted && !connected_to_server
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn tls_reason(err: &std::io::Error) -> Option<TlsErrorReason> {
+        match err.get_ref()?.downcast_ref::<Error>()? {
+            Error::Tls { reason } => Some(*reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn error_code_mapping() {
+        let err = resolve_io_error_from_error_code(ERROR_WINHTTP_NAME_NOT_RESOLVED);
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        let err = resolve_io_error_from_error_code(ERROR_WINHTTP_CANNOT_CONNECT);
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        let err = resolve_io_error_from_error_code(ERROR_WINHTTP_TIMEOUT);
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        for (code, reason) in [
+            (
+                ERROR_WINHTTP_SECURE_CERT_CN_INVALID,
+                TlsErrorReason::CertNameInvalid,
+            ),
+            (
+                ERROR_WINHTTP_SECURE_CERT_DATE_INVALID,
+                TlsErrorReason::CertExpired,
+            ),
+            (ERROR_WINHTTP_SECURE_INVALID_CA, TlsErrorReason::UntrustedCa),
+            (ERROR_WINHTTP_SECURE_FAILURE, TlsErrorReason::InvalidCert),
+        ] {
+            let err = resolve_io_error_from_error_code(code);
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+            assert_eq!(tls_reason(&err), Some(reason));
+        }
+    }
+
+    #[test]
+    fn stale_connection_errors() {
+        let aborted = resolve_io_error_from_error_code(ERROR_WINHTTP_CONNECTION_ERROR);
+        assert!(is_stale_connection_error(&aborted, false));
+        // Aborted on a connection opened for the request
+        assert!(!is_stale_connection_error(&aborted, true));
+        // A genuine connect failure is left to the connect retries
+        let refused = resolve_io_error_from_error_code(ERROR_WINHTTP_CANNOT_CONNECT);
+        assert!(!is_stale_connection_error(&refused, false));
+        let timed_out = resolve_io_error_from_error_code(ERROR_WINHTTP_TIMEOUT);
+        assert!(!is_stale_connection_error(&timed_out, false));
+    }
+
+    #[test]
+    fn secure_failure_flags() {
+        let err = resolve_secure_failure_flags(WINHTTP_CALLBACK_STATUS_FLAG_CERT_REVOKED);
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(tls_reason(&err), Some(TlsErrorReason::CertRevoked));
+    }
+}
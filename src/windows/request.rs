@@ -3,25 +3,265 @@ use std::future::Future;
 use std::{fmt::Debug, sync::mpsc::TryRecvError};
 use std::{pin::Pin, sync::Arc};
 use windows_sys::Win32::Networking::WinHttp::{
-    WinHttpAddRequestHeaders, WINHTTP_ADDREQ_FLAG_REPLACE,
+    WinHttpAddRequestHeaders, WINHTTP_ADDREQ_FLAG_REPLACE, WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH,
+    WINHTTP_OPTION_SECURITY_INFO,
+};
+use windows_sys::Win32::Security::Authentication::Identity::{
+    SecPkgContext_CipherInfo, SecPkgContext_ConnectionInfo, SP_PROT_TLS1_0_CLIENT,
+    SP_PROT_TLS1_1_CLIENT, SP_PROT_TLS1_2_CLIENT, SP_PROT_TLS1_3_CLIENT,
 };
 
-use self::err_code::resolve_io_error;
+use self::err_code::{is_stale_connection_error, resolve_io_error};
 
 use super::*;
 
-use crate::prelude::*;
+use crate::{
+    cancel::{cancelled_error, CancelState},
+    concurrency::{Permit, PermitWait},
+    error::validate_header,
+    prelude::*,
+    rate_limit::RateLimitWait,
+    response::{expected_body_size, join_header_values},
+    retry::{ReplayBody, RetryPolicy},
+    timer::{connect_retry_delay, Deadline, Delay},
+    trace::RequestSpan,
+    CancelHandle, HttpVersion, TaggedError, Timing, TlsInfo, TlsVersion,
+};
+
+/// Space reserved in front of each chunk for its hexadecimal size and CRLF
+const CHUNK_HEADER_SIZE: usize = 18;
+
+/// Wraps the `size` bytes read after [`CHUNK_HEADER_SIZE`] into a chunk of
+/// the chunked transfer encoding, and returns the whole framed chunk.
+fn frame_chunk(buf: &mut [u8], size: usize) -> &[u8] {
+    let header = format!("{:X}\r\n", size);
+    let start = CHUNK_HEADER_SIZE - header.len();
+    buf[start..CHUNK_HEADER_SIZE].copy_from_slice(header.as_bytes());
+    let end = CHUNK_HEADER_SIZE + size;
+    buf[end..end + 2].copy_from_slice(b"\r\n");
+    &buf[start..end + 2]
+}
+
+fn write_data(h_request: *mut c_void, data: &[u8]) -> std::io::Result<()> {
+    let r = unsafe {
+        WinHttpWriteData(
+            h_request,
+            data.as_ptr() as *const c_void,
+            data.len() as _,
+            std::ptr::null_mut(),
+        )
+    };
+    if r == 0 {
+        Err(resolve_io_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn receive_response(h_request: *mut c_void) -> std::io::Result<()> {
+    let r = unsafe { WinHttpReceiveResponse(h_request, std::ptr::null_mut()) };
+    if r == 0 {
+        Err(resolve_io_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Remove every value of a header added to the request, as replacing it with
+/// an empty value only removes the first one.
+fn remove_request_header(h_request: *mut c_void, header: &str) {
+    let headers = format!("{}:", header).to_utf16();
+    // Fails with `ERROR_WINHTTP_HEADER_NOT_FOUND` once none is left
+    while unsafe {
+        WinHttpAddRequestHeaders(
+            h_request,
+            headers.as_ptr(),
+            u32::MAX,
+            WINHTTP_ADDREQ_FLAG_REPLACE,
+        )
+    } != 0
+    {}
+}
+
+pub struct WinHTTPRequest {
+    pub(super) _connection: Arc<Handle>,
+    pub(super) h_request: Arc<Handle>,
+    pub(super) body: Box<dyn AsyncRead + Unpin + Send + Sync + 'static>,
+    pub(super) body_len: usize,
+    pub(super) chunked: bool,
+    pub(super) chunk_finished: bool,
+    pub(super) callback_receiver: Receiver<WinHTTPCallbackEvent>,
+    pub(super) buf: Box<[u8]>,
+    pub(super) ctx: Arc<NetworkContext>,
+    pub(super) responded: bool,
+    #[cfg(feature = "gzip")]
+    pub(super) compress_threshold: Option<usize>,
+    #[cfg(feature = "gzip")]
+    pub(super) gzip_body: bool,
+    pub(super) cancel: Arc<CancelState>,
+    pub(super) deadline: Option<Deadline>,
+    pub(super) rate_limit: Option<RateLimitWait>,
+    pub(super) concurrency: Option<PermitWait>,
+    pub(super) permit: Option<Permit>,
+    pub(super) connected: bool,
+    pub(super) connect_retries: u32,
+    pub(super) connect_attempts: u32,
+    pub(super) retry_delay: Option<Delay>,
+    pub(super) check_body_size: bool,
+    pub(super) max_response_size: Option<u64>,
+    pub(super) integrated_auth: bool,
+    pub(super) proxy_credentials: Option<Arc<(String, String)>>,
+    pub(super) digest_credentials: Option<Arc<(String, String)>>,
+    pub(super) url_credentials: Option<Arc<(String, String)>>,
+    pub(super) last_challenge: Option<u16>,
+    pub(super) idempotent: bool,
+    pub(super) head: bool,
+    pub(super) retry: Option<Arc<RetryPolicy>>,
+    pub(super) retry_attempts: u32,
+    pub(super) replay_body: Option<ReplayBody>,
+    pub(super) streamed_body: bool,
+    pub(super) method: Method,
+    pub(super) url: String,
+    pub(super) tag: Option<String>,
+    pub(super) span: RequestSpan,
+    pub(super) invalid_header: Option<crate::Error>,
+    /// The headers added to the handle, as WinHTTP can't tell them before
+    /// the request is sent.
+    pub(super) headers: Vec<(String, String)>,
+    pub(super) sent_at: Instant,
+}
 
-pin_project_lite::pin_project! {
-    pub struct WinHTTPRequest {
-        pub(super) _connection: Arc<Handle>,
-        pub(super) h_request: Arc<Handle>,
-        #[pin]
-        pub(super) body: Box<dyn AsyncRead + Unpin + Send + Sync + 'static>,
-        pub(super) body_len: usize,
-        pub(super) callback_receiver: Receiver<WinHTTPCallbackEvent>,
-        pub(super) buf: Pin<Box<[u8; BUF_SIZE]>>,
-        pub(super) ctx: Pin<Box<NetworkContext>>,
+/// The protocol negotiated for the request if it's HTTP/2 or HTTP/3, which
+/// the status line may not tell.
+fn protocol_used(h_request: *mut c_void) -> Option<HttpVersion> {
+    let mut flags = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let r = unsafe {
+        WinHttpQueryOption(
+            h_request,
+            WINHTTP_OPTION_HTTP_PROTOCOL_USED,
+            &mut flags as *mut u32 as *mut c_void,
+            &mut size,
+        )
+    };
+    if r == 0 {
+        None
+    } else if flags & WINHTTP_PROTOCOL_FLAG_HTTP3 != 0 {
+        Some(HttpVersion::Http3)
+    } else if flags & WINHTTP_PROTOCOL_FLAG_HTTP2 != 0 {
+        Some(HttpVersion::Http2)
+    } else {
+        None
+    }
+}
+
+/// `WINHTTP_SECURITY_INFO`, which `windows-sys` doesn't define.
+#[repr(C)]
+struct SecurityInfo {
+    connection_info: SecPkgContext_ConnectionInfo,
+    cipher_info: SecPkgContext_CipherInfo,
+}
+
+/// The TLS version and cipher suite of the connection of the request, `None`
+/// if it isn't encrypted.
+fn security_info(h_request: *mut c_void) -> Option<TlsInfo> {
+    let mut info: SecurityInfo = unsafe { std::mem::zeroed() };
+    let mut size = std::mem::size_of::<SecurityInfo>() as u32;
+    let r = unsafe {
+        WinHttpQueryOption(
+            h_request,
+            WINHTTP_OPTION_SECURITY_INFO,
+            &mut info as *mut SecurityInfo as *mut c_void,
+            &mut size,
+        )
+    };
+    if r == 0 {
+        return None;
+    }
+    let version = match info.connection_info.dwProtocol {
+        SP_PROT_TLS1_0_CLIENT => TlsVersion::Tls1_0,
+        SP_PROT_TLS1_1_CLIENT => TlsVersion::Tls1_1,
+        SP_PROT_TLS1_2_CLIENT => TlsVersion::Tls1_2,
+        SP_PROT_TLS1_3_CLIENT => TlsVersion::Tls1_3,
+        _ => return None,
+    };
+    let suite = &info.cipher_info.szCipherSuite;
+    let len = suite.iter().position(|x| *x == 0).unwrap_or(suite.len());
+    Some(TlsInfo {
+        version,
+        cipher: String::from_utf16_lossy(&suite[..len]),
+    })
+}
+
+/// Set the credentials for the challenge of the response, returns `false` if
+/// none of its schemes can be answered.
+///
+/// Without a user name and a password, the credentials of the current user
+/// are used, which only works for NTLM and Negotiate.
+///
+/// With `digest_only`, the credentials are only sent with Digest, so they
+/// never go in plain text over Basic.
+fn set_credentials(
+    h_request: *mut c_void,
+    credentials: Option<&(String, String)>,
+    digest_only: bool,
+) -> std::io::Result<bool> {
+    let mut supported = 0;
+    let mut first = 0;
+    let mut target = 0;
+    unsafe {
+        if WinHttpQueryAuthSchemes(h_request, &mut supported, &mut first, &mut target) == 0 {
+            return Err(resolve_io_error());
+        }
+    }
+    // From the most secure scheme to the least
+    let schemes: &[u32] = if digest_only {
+        &[WINHTTP_AUTH_SCHEME_DIGEST]
+    } else if credentials.is_some() {
+        &[
+            WINHTTP_AUTH_SCHEME_NEGOTIATE,
+            WINHTTP_AUTH_SCHEME_NTLM,
+            WINHTTP_AUTH_SCHEME_DIGEST,
+            WINHTTP_AUTH_SCHEME_BASIC,
+        ]
+    } else {
+        &[WINHTTP_AUTH_SCHEME_NEGOTIATE, WINHTTP_AUTH_SCHEME_NTLM]
+    };
+    let Some(scheme) = schemes.iter().copied().find(|x| supported & x != 0) else {
+        return Ok(false);
+    };
+    let (user, pass) = match credentials {
+        Some((user, pass)) => (
+            Some(user.as_str().to_utf16()),
+            Some(pass.as_str().to_utf16()),
+        ),
+        None => (None, None),
+    };
+    let r = unsafe {
+        WinHttpSetCredentials(
+            h_request,
+            target,
+            scheme,
+            user.as_ref().map_or(std::ptr::null(), |x| x.as_ptr()),
+            pass.as_ref().map_or(std::ptr::null(), |x| x.as_ptr()),
+            std::ptr::null_mut(),
+        )
+    };
+    if r == 0 {
+        return Err(resolve_io_error());
+    }
+    Ok(true)
+}
+
+impl Drop for WinHTTPRequest {
+    fn drop(&mut self) {
+        // Once responded, the context and the handle belong to the response
+        if !self.responded {
+            // Abort the transfer, the buffer may still be in use until WinHTTP
+            // stops calling back
+            self.h_request.close();
+            self.ctx.keep_buffer(std::mem::take(&mut self.buf));
+        }
     }
 }
 
@@ -31,8 +271,10 @@ impl Debug for WinHTTPRequest {
             .field("connection", &self._connection)
             .field("h_request", &self.h_request)
             .field("body_len", &self.body_len)
+            .field("chunked", &self.chunked)
             .field("callback_receiver", &self.callback_receiver)
             .field("ctx", &self.ctx)
+            .field("cancel", &self.cancel)
             .finish()
     }
 }
@@ -43,45 +285,292 @@ impl CommonRequest for WinHTTPRequest {
         body: impl AsyncRead + Unpin + Send + Sync + 'static,
         body_size: usize,
     ) -> Self {
+        #[cfg(feature = "gzip")]
+        if self.gzip_body {
+            // Don't compress the compressed body again
+            self.gzip_body = false;
+            let mut req = crate::compress::set_gzip_body(self, body);
+            req.gzip_body = true;
+            return req;
+        }
         self.body_len = body_size;
+        self.replay_body = None;
+        self.streamed_body = true;
+        // Keep buffered bodies to send them again when retrying
+        self.body = match self.retry {
+            Some(_) => match ReplayBody::from_body(body) {
+                Ok(body) => {
+                    self.streamed_body = false;
+                    let reader = Box::new(body.reader());
+                    self.replay_body = Some(body);
+                    reader
+                }
+                Err(body) => Box::new(body),
+            },
+            None => Box::new(body),
+        };
+        if self.chunked {
+            self.chunked = false;
+            // Replacing with an empty value removes the header
+            self = self.replace_header("Transfer-Encoding", "");
+        }
+        self
+    }
+
+    fn body_empty(self) -> Self {
+        // WinHTTP only adds `Content-Length` when there's data to send
+        self.body(futures_lite::io::empty(), 0)
+            .replace_header("Content-Length", "0")
+    }
+
+    fn body_chunked(mut self, body: impl AsyncRead + Unpin + Send + Sync + 'static) -> Self {
+        #[cfg(feature = "gzip")]
+        if self.gzip_body {
+            // Don't compress the compressed body again
+            self.gzip_body = false;
+            let mut req = crate::compress::set_gzip_body(self, body);
+            req.gzip_body = true;
+            return req;
+        }
+        self.body_len = 0;
         self.body = Box::new(body);
+        self.replay_body = None;
+        self.streamed_body = true;
+        if !self.chunked {
+            self.chunked = true;
+            self = self.replace_header("Transfer-Encoding", "chunked");
+        }
         self
     }
 
-    fn header(self, header: &str, value: &str) -> Self {
-        let headers = format!("{}:{}", header, value);
-        let headers = headers.to_utf16().as_ptr();
+    #[cfg(feature = "gzip")]
+    fn gzip_body(mut self) -> Self {
+        if self.gzip_body {
+            return self;
+        }
+        self = self.replace_header("Content-Encoding", "gzip");
+        self.gzip_body = true;
+        // Compress the body provided before
+        if let Some(body) = self.replay_body.take() {
+            let data = body.as_ref().to_vec();
+            let len = data.len();
+            self = self.body(futures_lite::io::Cursor::new(data), len);
+        } else if self.streamed_body && (self.body_len > 0 || self.chunked) {
+            let body = std::mem::replace(&mut self.body, Box::new(futures_lite::io::empty()));
+            self = self.body_chunked(body);
+        } else {
+            // Even an empty body has to be a gzip stream
+            self = self.body_bytes(Vec::new());
+        }
+        self
+    }
+
+    #[cfg(feature = "gzip")]
+    fn body_bytes(self, body: Vec<u8>) -> Self {
+        // The body is compressed by itself already
+        let threshold = self.compress_threshold.filter(|_| !self.gzip_body);
+        crate::compress::body_bytes_compressed(self, threshold, body)
+    }
+
+    fn header(mut self, header: &str, value: &str) -> Self {
+        if let Err(err) = validate_header(header, value) {
+            self.invalid_header.get_or_insert(err);
+            return self;
+        }
+        let headers = format!("{}:{}", header, value).to_utf16();
 
         unsafe {
-            WinHttpAddRequestHeaders(**self.h_request, headers, u32::MAX, WINHTTP_ADDREQ_FLAG_ADD);
+            WinHttpAddRequestHeaders(
+                **self.h_request,
+                headers.as_ptr(),
+                u32::MAX,
+                WINHTTP_ADDREQ_FLAG_ADD,
+            );
         }
+        self.headers.push((header.to_owned(), value.to_owned()));
 
         self
     }
 
-    fn replace_header(self, header: &str, value: &str) -> Self {
-        let headers = format!("{}:{}", header, value);
-        let headers = headers.to_utf16().as_ptr();
+    fn replace_header(mut self, header: &str, value: &str) -> Self {
+        if let Err(err) = validate_header(header, value) {
+            self.invalid_header.get_or_insert(err);
+            return self;
+        }
+        remove_request_header(**self.h_request, header);
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+        // An empty value removes the header
+        if value.is_empty() {
+            return self;
+        }
+        let headers = format!("{}:{}", header, value).to_utf16();
 
         unsafe {
             WinHttpAddRequestHeaders(
                 **self.h_request,
-                headers,
+                headers.as_ptr(),
                 u32::MAX,
-                WINHTTP_ADDREQ_FLAG_REPLACE,
+                WINHTTP_ADDREQ_FLAG_ADD,
             );
         }
+        self.headers.push((header.to_owned(), value.to_owned()));
+
+        self
+    }
+
+    fn remove_header(mut self, header: &str) -> Self {
+        if let Err(err) = validate_header(header, "") {
+            self.invalid_header.get_or_insert(err);
+            return self;
+        }
+        remove_request_header(**self.h_request, header);
+        self.headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+
+        self
+    }
+
+    fn get_header(&self, header: &str) -> Option<String> {
+        join_header_values(&self.headers, header)
+    }
+
+    fn deadline(mut self, at: std::time::Instant) -> Self {
+        self.deadline = Some(Deadline::new(at));
+        self
+    }
+
+    fn no_timeout(self) -> Self {
+        // Zero means waiting infinitely for every phase
+        unsafe {
+            WinHttpSetTimeouts(**self.h_request, 0, 0, 0, 0);
+        }
+
+        self
+    }
 
+    fn windows_integrated_auth(mut self) -> Self {
+        // The default autologon policy decides which servers get the
+        // credentials of the current user, never lower it
+        self.integrated_auth = true;
         self
     }
+
+    fn digest_auth(mut self, user: &str, pass: &str) -> Self {
+        self.digest_credentials = Some(Arc::new((user.to_owned(), pass.to_owned())));
+        self
+    }
+
+    fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    fn cancellable(self) -> (Self, CancelHandle) {
+        // The state is also cancelled by the shutdown of the client
+        let handle = CancelHandle {
+            state: self.cancel.clone(),
+        };
+        (self, handle)
+    }
 }
 
 impl Future for WinHTTPRequest {
     type Output = futures_lite::io::Result<WinHTTPResponse>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
-        if self.ctx.as_mut().waker.is_none() {
-            self.ctx.as_mut().waker = Some(cx.waker().clone());
+        let this = &mut *self;
+        this.span.start(this.method, &this.url, this.tag.as_deref());
+        match self.as_mut().poll_response(cx) {
+            Poll::Ready(Err(err)) => {
+                self.span.failed(&err);
+                Poll::Ready(Err(match &self.tag {
+                    Some(tag) => TaggedError::tag_io_error(tag, err),
+                    None => err,
+                }))
+            }
+            Poll::Ready(Ok(res)) => {
+                self.span.response(res.code, &res.timing);
+                Poll::Ready(Ok(res))
+            }
+            other => other,
+        }
+    }
+}
+
+impl WinHTTPRequest {
+    /// The retry policy if the request can be sent again, which needs it to be
+    /// idempotent and its body to be replayable.
+    fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_deref().filter(|policy| {
+            policy.has_attempts_left(self.retry_attempts + 1) && self.can_send_again()
+        })
+    }
+
+    /// Whether the request is idempotent and its body can be sent again.
+    fn can_send_again(&self) -> bool {
+        self.idempotent && (self.replay_body.is_some() || !self.streamed_body)
+    }
+
+    /// Retry the request after `delay`, as an attempt of the retry policy.
+    fn retry_after(&mut self, delay: Duration, cx: &mut std::task::Context<'_>) {
+        self.retry_attempts += 1;
+        self.send_again(delay, cx);
+    }
+
+    /// Send the request again on the same handle after `delay`.
+    fn send_again(&mut self, delay: Duration, cx: &mut std::task::Context<'_>) {
+        if let Some(body) = &self.replay_body {
+            self.body = Box::new(body.reader());
+        }
+        self.chunk_finished = false;
+        self.retry_delay = Some(Delay::new(delay));
+        self.ctx.set_waker(None);
+        cx.waker().wake_by_ref();
+    }
+
+    fn poll_response(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<WinHTTPResponse>> {
+        if let Some(err) = self.invalid_header.take() {
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                err,
+            )));
+        }
+        if self.cancel.is_cancelled() {
+            return Poll::Ready(Err(cancelled_error()));
+        }
+        self.cancel.register(cx.waker());
+        if let Some(Err(err)) = self.deadline.as_mut().map(|x| x.check(cx)) {
+            // Stop the transfer instead of letting it run in the background
+            self.h_request.close();
+            return Poll::Ready(Err(err));
+        }
+        if let Some(retry_delay) = &mut self.retry_delay {
+            if Pin::new(retry_delay).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.retry_delay = None;
+        }
+        if !self.ctx.has_waker() {
+            if self.permit.is_none() {
+                if let Some(concurrency) = &mut self.concurrency {
+                    match concurrency.poll_acquire(cx) {
+                        Poll::Ready(permit) => self.permit = Some(permit),
+                        Poll::Pending => return Poll::Pending,
+                    }
+                }
+            }
+            if let Some(rate_limit) = &mut self.rate_limit {
+                if rate_limit.poll_acquire(cx).is_pending() {
+                    return Poll::Pending;
+                }
+            }
+            self.ctx.set_waker(Some(cx.waker().clone()));
+            self.ctx.set_connected_to_server(false);
+            self.sent_at = Instant::now();
             let send_result = unsafe {
                 WinHttpSendRequest(
                     **self.h_request,
@@ -89,68 +578,195 @@ impl Future for WinHTTPRequest {
                     0,
                     std::ptr::null(),
                     0,
-                    self.body_len as _,
-                    self.ctx.as_mut().get_unchecked_mut() as *mut _ as usize,
+                    if self.chunked {
+                        WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH
+                    } else {
+                        self.body_len as _
+                    },
+                    Arc::as_ptr(&self.ctx) as usize,
                 )
             };
             if send_result == 0 {
                 return Poll::Ready(Err(resolve_io_error()));
             }
+        } else {
+            self.ctx.refresh_waker(cx.waker());
         }
         match self.callback_receiver.try_recv() {
             Ok(event) => match event {
                 WinHTTPCallbackEvent::WriteCompleted => {
-                    let project = self.project();
-                    match project.body.poll_read(cx, project.buf.as_mut_slice()) {
+                    let project = &mut *self;
+                    project.connected = true;
+                    let h_request = **project.h_request;
+                    if project.chunk_finished {
+                        return match receive_response(h_request) {
+                            Ok(()) => Poll::Pending,
+                            Err(err) => Poll::Ready(Err(err)),
+                        };
+                    }
+                    // Leave room around the data for the chunk size line and
+                    // the trailing CRLF when sending a chunked body
+                    let read_buf = if project.chunked {
+                        let len = project.buf.len();
+                        &mut project.buf[CHUNK_HEADER_SIZE..len - 2]
+                    } else {
+                        &mut project.buf[..]
+                    };
+                    match Pin::new(&mut project.body).poll_read(cx, read_buf) {
                         Poll::Ready(Ok(size)) => {
-                            if size == 0 {
-                                let h_request = ***project.h_request;
-                                let r = unsafe {
-                                    WinHttpReceiveResponse(h_request, std::ptr::null_mut())
-                                };
-                                if r == 0 {
-                                    return Poll::Ready(Err(resolve_io_error()));
-                                }
+                            let r = if project.chunked {
+                                // The last chunk is empty, we still need to
+                                // write it out before receiving the response
+                                project.chunk_finished = size == 0;
+                                let chunk = frame_chunk(&mut project.buf, size);
+                                write_data(h_request, chunk)
+                            } else if size == 0 {
+                                receive_response(h_request)
                             } else {
-                                let h_request = ***project.h_request;
-                                let buf = project.buf.as_ptr();
-                                let r = unsafe {
-                                    WinHttpWriteData(
-                                        h_request,
-                                        buf as *const c_void,
-                                        size as _,
-                                        std::ptr::null_mut(),
-                                    )
-                                };
-                                if r == 0 {
-                                    return Poll::Ready(Err(resolve_io_error()));
-                                }
+                                write_data(h_request, &project.buf[..size])
+                            };
+                            match r {
+                                Ok(()) => Poll::Pending,
+                                Err(err) => Poll::Ready(Err(err)),
                             }
-                            Poll::Pending
                         }
                         Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
                         Poll::Pending => Poll::Pending,
                     }
                 }
                 WinHTTPCallbackEvent::RawHeadersReceived(raw_headers) => {
-                    let (ctx, mut rx) = NetworkContext::new();
-                    let mut ctx = Box::pin(ctx);
-                    std::mem::swap(&mut ctx, &mut self.ctx);
-                    std::mem::swap(&mut rx, &mut self.callback_receiver);
-                    ctx.waker = None;
-                    ctx.buf_size = usize::MAX;
+                    let RawHead {
+                        code,
+                        version,
+                        reason,
+                        headers,
+                        ordered_headers,
+                    } = parse_raw_headers(&raw_headers);
+                    let credentials = match code {
+                        // Credentials of the URL answer any scheme, like
+                        // other clients do
+                        401 => self
+                            .digest_credentials
+                            .clone()
+                            .or_else(|| self.url_credentials.clone()),
+                        407 => self.proxy_credentials.clone(),
+                        _ => None,
+                    };
+                    let digest_only = code == 401 && self.digest_credentials.is_some();
+                    let answerable = (code == 401 || code == 407)
+                        && (self.integrated_auth || credentials.is_some());
+                    // The body has been consumed, so only requests without
+                    // one can be sent again with the credentials. Give up if
+                    // the same challenge comes back after answered.
+                    if answerable
+                        && self.last_challenge != Some(code)
+                        && self.body_len == 0
+                        && !self.chunked
+                    {
+                        self.last_challenge = Some(code);
+                        match set_credentials(**self.h_request, credentials.as_deref(), digest_only)
+                        {
+                            Ok(true) => {
+                                self.ctx.set_waker(None);
+                                cx.waker().wake_by_ref();
+                                return Poll::Pending;
+                            }
+                            Ok(false) => {}
+                            Err(err) => return Poll::Ready(Err(err)),
+                        }
+                    }
+                    let retry_after = crate::response::find_header(&headers, "Retry-After");
+                    if let Some(rate_limit) = &self.rate_limit {
+                        rate_limit.limiter().handle_response(code, retry_after);
+                    }
+                    let retry_delay = self
+                        .retry_policy()
+                        .filter(|policy| policy.should_retry_status(code))
+                        .map(|policy| policy.delay(self.retry_attempts + 1, retry_after));
+                    if let Some(delay) = retry_delay {
+                        self.retry_after(delay, cx);
+                        return Poll::Pending;
+                    }
+                    let ctx = self.ctx.clone();
+                    let rx = std::mem::replace(
+                        &mut self.callback_receiver,
+                        std::sync::mpsc::channel().1,
+                    );
+                    ctx.set_waker(None);
+                    ctx.set_buf_size(usize::MAX);
+                    self.responded = true;
                     Poll::Ready(Ok(WinHTTPResponse {
                         _connection: self._connection.clone(),
                         h_request: self.h_request.clone(),
                         ctx,
                         read_size: 0,
                         total_read_size: 0,
-                        buf: Box::pin([0; BUF_SIZE]),
-                        raw_headers,
+                        expected_size: if self.check_body_size {
+                            expected_body_size(code, &headers)
+                        } else {
+                            None
+                        },
+                        max_size: self.max_response_size,
+                        no_body: self.head
+                            || (100..200).contains(&code)
+                            || code == 204
+                            || code == 304,
+                        buf: vec![0; self.buf.len()].into_boxed_slice(),
+                        code,
+                        version: protocol_used(**self.h_request)
+                            .or(version)
+                            .unwrap_or(HttpVersion::Http11),
+                        reason,
+                        headers,
+                        ordered_headers,
+                        raw_headers: query_raw_headers(**self.h_request, &raw_headers),
+                        tls_info: security_info(**self.h_request),
                         callback_receiver: rx,
+                        permit: self.permit.take(),
+                        cancel: self.cancel.clone(),
+                        deadline: self.deadline.clone(),
+                        sent_at: self.sent_at,
+                        timing: Timing::new(self.sent_at),
+                        span: self.span.clone(),
                     }))
                 }
-                WinHTTPCallbackEvent::Error(err) => Poll::Ready(Err(err)),
+                // Failed to connect, nothing has been sent yet so it can be
+                // sent again on the same handle
+                WinHTTPCallbackEvent::Error(err)
+                    if err.kind() == std::io::ErrorKind::NotConnected
+                        && !self.connected
+                        && self.connect_attempts < self.connect_retries =>
+                {
+                    self.connect_attempts += 1;
+                    self.retry_delay = Some(Delay::new(connect_retry_delay(self.connect_attempts)));
+                    self.ctx.set_waker(None);
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                // The server may have closed the kept-alive connection since
+                // its last use, send the request once more, WinHTTP drops the
+                // closed connection and opens a new one. It counts as one of
+                // the connect retries, so it's only done once.
+                WinHTTPCallbackEvent::Error(err)
+                    if is_stale_connection_error(&err, self.ctx.connected_to_server())
+                        && self.connect_attempts == 0
+                        && self.can_send_again() =>
+                {
+                    self.connect_attempts += 1;
+                    self.send_again(Duration::ZERO, cx);
+                    Poll::Pending
+                }
+                WinHTTPCallbackEvent::Error(err) => {
+                    let retry_delay = self
+                        .retry_policy()
+                        .filter(|policy| policy.should_retry_error(err.kind()))
+                        .map(|policy| policy.delay(self.retry_attempts + 1, None));
+                    if let Some(delay) = retry_delay {
+                        self.retry_after(delay, cx);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Err(err))
+                }
                 _ => unreachable!(),
             },
             Err(TryRecvError::Empty) => Poll::Pending,
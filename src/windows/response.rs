@@ -1,101 +1,344 @@
 use futures_lite::*;
 use std::{
+    borrow::Cow,
     collections::HashMap,
+    ffi::c_void,
     pin::Pin,
     sync::{
         mpsc::{Receiver, TryRecvError},
         Arc,
     },
     task::Poll,
+    time::Instant,
 };
-use windows_sys::Win32::Networking::WinHttp::{WinHttpQueryDataAvailable, WinHttpReadData};
+use windows_sys::Win32::Networking::WinHttp::{
+    WinHttpQueryDataAvailable, WinHttpQueryHeaders, WinHttpReadData, WINHTTP_QUERY_FLAG_TRAILERS,
+    WINHTTP_QUERY_FLAG_WIRE_ENCODING, WINHTTP_QUERY_RAW_HEADERS_CRLF,
+};
+
+use super::{err_code::resolve_io_error, Handle, NetworkContext, WinHTTPCallbackEvent};
+use crate::{
+    cancel::{cancelled_error, CancelState},
+    concurrency::Permit,
+    prelude::*,
+    response::{check_body_limit, insert_header, split_raw_header},
+    timer::Deadline,
+    trace::RequestSpan,
+    HttpVersion, ResponseBody, Timing, TlsInfo,
+};
+
+/// The head of a response parsed from the raw headers queried from WinHTTP.
+pub(super) struct RawHead {
+    pub(super) code: u16,
+    pub(super) version: Option<HttpVersion>,
+    pub(super) reason: Option<Cow<'static, str>>,
+    pub(super) headers: HashMap<String, String>,
+    /// The headers in the order they were received.
+    pub(super) ordered_headers: Vec<(String, String)>,
+}
+
+/// Parse the status code, version, reason phrase and headers from the raw
+/// headers queried from WinHTTP.
+pub(super) fn parse_raw_headers(raw_headers: &str) -> RawHead {
+    let mut headers_lines = raw_headers.lines();
+
+    // Status line looks like `HTTP/1.1 404 Not Found`
+    let mut status_line = headers_lines.next().unwrap_or_default().splitn(3, ' ');
+    let version = status_line
+        .next()
+        .and_then(|x| x.strip_prefix("HTTP/"))
+        .and_then(HttpVersion::parse);
+    let status_code = status_line
+        .next()
+        .map(|x| x.parse::<u16>().unwrap_or(0))
+        .unwrap_or(0);
+    let reason = status_line
+        .next()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| Cow::Owned(x.to_owned()));
 
-use super::{err_code::resolve_io_error, Handle, NetworkContext, WinHTTPCallbackEvent, BUF_SIZE};
-use crate::{prelude::*, ResponseBody};
+    let mut parsed_headers: HashMap<String, String> =
+        HashMap::with_capacity(headers_lines.size_hint().1.unwrap_or(8));
+    let mut ordered_headers = Vec::with_capacity(headers_lines.size_hint().1.unwrap_or(8));
+
+    for header in headers_lines {
+        if let Some((key, value)) = header.split_once(": ") {
+            let key = key.trim();
+            let value = value.trim();
+            ordered_headers.push((key.to_owned(), value.to_owned()));
+            insert_header(&mut parsed_headers, key.to_owned(), value);
+        }
+    }
+
+    RawHead {
+        code: status_code,
+        version,
+        reason,
+        headers: parsed_headers,
+        ordered_headers,
+    }
+}
+
+/// The headers of the final response with their values as the bytes sent by
+/// the server, queried in the wire encoding. If WinHTTP is too old to support
+/// it, the values decoded in `fallback` are used instead.
+pub(super) fn query_raw_headers(h_request: *mut c_void, fallback: &str) -> Vec<(String, Vec<u8>)> {
+    let level = WINHTTP_QUERY_RAW_HEADERS_CRLF | WINHTTP_QUERY_FLAG_WIRE_ENCODING;
+    let mut size = 0;
+    unsafe {
+        WinHttpQueryHeaders(
+            h_request,
+            level,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            &mut size,
+            std::ptr::null_mut(),
+        );
+    }
+    let mut data = vec![0u8; size as usize];
+    let r = size > 0
+        && unsafe {
+            WinHttpQueryHeaders(
+                h_request,
+                level,
+                std::ptr::null(),
+                data.as_mut_ptr() as *mut c_void,
+                &mut size,
+                std::ptr::null_mut(),
+            )
+        } != 0;
+    let data = if r {
+        &data[..size as usize]
+    } else {
+        fallback.as_bytes()
+    };
+    data.split(|&x| x == b'\n')
+        // Skip the status line
+        .skip(1)
+        .filter_map(split_raw_header)
+        .collect()
+}
+
+/// The trailers received after the body, only known once the body has been
+/// read to the end. Empty if there's none, or if WinHTTP is too old to
+/// support them.
+fn query_trailers(h_request: *mut c_void) -> HashMap<String, String> {
+    let mut size = 0;
+    unsafe {
+        WinHttpQueryHeaders(
+            h_request,
+            WINHTTP_QUERY_RAW_HEADERS_CRLF | WINHTTP_QUERY_FLAG_TRAILERS,
+            std::ptr::null(),
+            std::ptr::null_mut(),
+            &mut size,
+            std::ptr::null_mut(),
+        );
+    }
+    if size == 0 {
+        return HashMap::new();
+    }
+    let mut data = vec![0u16; size as usize / 2];
+    let r = unsafe {
+        WinHttpQueryHeaders(
+            h_request,
+            WINHTTP_QUERY_RAW_HEADERS_CRLF | WINHTTP_QUERY_FLAG_TRAILERS,
+            std::ptr::null(),
+            data.as_mut_ptr() as *mut c_void,
+            &mut size,
+            std::ptr::null_mut(),
+        )
+    };
+    if r == 0 {
+        return HashMap::new();
+    }
+    parse_trailers(&String::from_utf16_lossy(&data))
+}
+
+/// Parse the trailer lines, with their names lowercased like curl does.
+fn parse_trailers(lines: &str) -> HashMap<String, String> {
+    lines
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| {
+            (
+                name.trim().to_lowercase(),
+                value.trim_matches([' ', '\t', '\0']).to_owned(),
+            )
+        })
+        .collect()
+}
 
 pub struct WinHTTPResponse {
     pub(super) _connection: Arc<Handle>,
     pub(super) h_request: Arc<Handle>,
-    pub(super) raw_headers: String,
-    pub(super) ctx: Pin<Box<NetworkContext>>,
-    pub(super) buf: Pin<Box<[u8; BUF_SIZE]>>,
+    pub(super) code: u16,
+    pub(super) version: HttpVersion,
+    pub(super) reason: Option<Cow<'static, str>>,
+    pub(super) headers: HashMap<String, String>,
+    pub(super) ordered_headers: Vec<(String, String)>,
+    pub(super) raw_headers: Vec<(String, Vec<u8>)>,
+    pub(super) tls_info: Option<TlsInfo>,
+    pub(super) ctx: Arc<NetworkContext>,
+    pub(super) buf: Box<[u8]>,
     pub(super) read_size: usize,
     pub(super) total_read_size: usize,
+    pub(super) expected_size: Option<u64>,
+    pub(super) max_size: Option<u64>,
+    /// The response has no body by definition, like the one of a `HEAD`
+    /// request or `204 No Content`, so reading it ends right away instead of
+    /// waiting for WinHTTP to report data available.
+    pub(super) no_body: bool,
     pub(super) callback_receiver: Receiver<WinHTTPCallbackEvent>,
+    pub(super) permit: Option<Permit>,
+    pub(super) cancel: Arc<CancelState>,
+    pub(super) deadline: Option<Deadline>,
+    pub(super) sent_at: Instant,
+    pub(super) timing: Timing,
+    pub(super) span: RequestSpan,
+}
+
+impl Drop for WinHTTPResponse {
+    fn drop(&mut self) {
+        // Abort the transfer, the buffer may still be in use until WinHTTP
+        // stops calling back
+        self.h_request.close();
+        self.ctx.keep_buffer(std::mem::take(&mut self.buf));
+    }
 }
 
 #[cfg_attr(feature = "async_t", async_t::async_trait)]
 impl CommonResponse for WinHTTPResponse {
+    fn status_code(&self) -> u16 {
+        self.code
+    }
+
+    fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    fn http_version(&self) -> HttpVersion {
+        self.version
+    }
+
+    fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    fn tls_info(&self) -> Option<TlsInfo> {
+        self.tls_info.clone()
+    }
+
+    fn buffered(self) -> impl AsyncBufRead + Unpin {
+        self
+    }
+
+    async fn trailers(mut self) -> std::io::Result<HashMap<String, String>> {
+        futures_lite::io::copy(&mut self, &mut futures_lite::io::sink()).await?;
+        Ok(query_trailers(**self.h_request))
+    }
+
+    async fn copy_to<W: AsyncWrite + Unpin>(mut self, writer: &mut W) -> std::io::Result<u64> {
+        // Write straight from the buffer filled by WinHTTP
+        let mut total = 0;
+        while futures_lite::future::poll_fn(|cx| self.poll_fill(cx)).await? {
+            let (start, end) = (self.read_size, self.ctx.buf_size());
+            writer.write_all(&self.buf[start..end]).await?;
+            self.read_size = end;
+            self.total_read_size += end - start;
+            total += (end - start) as u64;
+        }
+        writer.flush().await?;
+        Ok(total)
+    }
+
+    #[cfg(feature = "bytes")]
+    async fn chunk(&mut self) -> std::io::Result<Option<bytes::Bytes>> {
+        futures_lite::future::poll_fn(|cx| self.poll_chunk(cx)).await
+    }
+
     async fn recv(mut self) -> std::io::Result<ResponseBody> {
-        let mut data = Vec::with_capacity(256);
+        let mut data = Vec::with_capacity(crate::response::body_capacity(self.expected_size, 256));
         self.read_to_end(&mut data).await?;
         data.shrink_to_fit();
-        let mut headers_lines = self.raw_headers.lines();
-
-        let status_code = headers_lines
-            .next()
-            .and_then(|x| x.split(' ').nth(1).map(|x| x.parse::<u16>().unwrap_or(0)))
-            .unwrap_or(0);
-
-        let mut parsed_headers: HashMap<String, String> =
-            HashMap::with_capacity(headers_lines.size_hint().1.unwrap_or(8));
-
-        for header in headers_lines {
-            if let Some((key, value)) = header.split_once(": ") {
-                let key = key.trim();
-                let value = value.trim();
-                if let Some(exist_header) = parsed_headers.get_mut(key) {
-                    exist_header.push_str("; ");
-                    exist_header.push_str(value);
-                } else {
-                    parsed_headers.insert(key.to_owned(), value.to_owned());
-                }
-            }
-        }
-
         Ok(ResponseBody {
             data,
-            code: status_code,
-            headers: parsed_headers,
+            code: self.code,
+            reason: self.reason.take(),
+            headers: std::mem::take(&mut self.ordered_headers),
+            raw_headers: std::mem::take(&mut self.raw_headers),
+            trailers: query_trailers(**self.h_request),
+            timing: self.timing,
         })
     }
 }
 
-impl AsyncRead for WinHTTPResponse {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut [u8],
-    ) -> Poll<futures_lite::io::Result<usize>> {
-        if self.ctx.as_mut().waker.is_none() {
-            self.ctx.as_mut().waker = Some(cx.waker().clone());
+impl WinHTTPResponse {
+    /// Called when the body ends, let other requests of the client go and
+    /// make sure the whole body has been received.
+    fn finish_body(&mut self) -> std::io::Result<()> {
+        self.permit = None;
+        if self.timing.total().is_none() {
+            self.timing.finish(self.sent_at);
+            self.span.finished(&self.timing);
+        }
+        match self.expected_size {
+            Some(expected) if (self.total_read_size as u64) < expected => Err(
+                crate::response::truncated_body_error(self.total_read_size as u64, expected),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait until there's unread data in the buffer, resolves to `false` at
+    /// the end of the body.
+    fn poll_fill(&mut self, cx: &mut std::task::Context<'_>) -> Poll<std::io::Result<bool>> {
+        if self.cancel.is_cancelled() {
+            return Poll::Ready(Err(cancelled_error()));
+        }
+        self.cancel.register(cx.waker());
+        if let Some(Err(err)) = self.deadline.as_mut().map(|x| x.check(cx)) {
+            // Stop the transfer instead of letting it run in the background
+            self.h_request.close();
+            return Poll::Ready(Err(err));
+        }
+        if self.no_body {
+            return Poll::Ready(self.finish_body().map(|_| false));
+        }
+        if !self.ctx.has_waker() {
+            self.ctx.set_waker(Some(cx.waker().clone()));
             let r = unsafe { WinHttpQueryDataAvailable(**self.h_request, std::ptr::null_mut()) };
             if r == 0 {
                 return Poll::Ready(Err(resolve_io_error()));
             }
+        } else {
+            self.ctx.refresh_waker(cx.waker());
         }
-        if self.ctx.has_completed {
-            return Poll::Ready(Ok(0));
+        if self.ctx.has_completed() {
+            return Poll::Ready(self.finish_body().map(|_| false));
         }
-        if self.ctx.buf_size != usize::MAX && self.read_size < self.ctx.buf_size {
-            let read_size = self
-                .ctx
-                .buf_size
-                .min(buf.len())
-                .min(self.ctx.buf_size - self.read_size);
-            buf[..read_size].copy_from_slice(&self.buf[self.read_size..self.read_size + read_size]);
-            self.read_size += read_size;
-            self.total_read_size += read_size;
-            return Poll::Ready(Ok(read_size));
+        let buf_size = self.ctx.buf_size();
+        if buf_size != usize::MAX && self.read_size < buf_size {
+            let received = (self.total_read_size + buf_size - self.read_size) as u64;
+            if let Err(err) = check_body_limit(received, self.expected_size, self.max_size) {
+                return Poll::Ready(Err(err));
+            }
+            return Poll::Ready(Ok(true));
         }
         match self.callback_receiver.try_recv() {
             Ok(event) => {
                 let result = match event {
-                    WinHTTPCallbackEvent::DataAvailable => {
+                    // Nothing left, like an empty body, so there's no need to
+                    // read it out
+                    WinHTTPCallbackEvent::DataAvailable(0) => {
+                        self.ctx.set_completed();
+                        Poll::Ready(self.finish_body().map(|_| false))
+                    }
+                    WinHTTPCallbackEvent::DataAvailable(_) => {
                         self.read_size = 0;
-                        self.ctx.buf_size = usize::MAX;
+                        self.ctx.set_buf_size(usize::MAX);
                         let h_request = **self.h_request;
-                        let buf = self.buf.as_mut_slice();
+                        let buf = &mut self.buf;
                         let r = unsafe {
                             WinHttpReadData(
                                 h_request,
@@ -110,8 +353,8 @@ impl AsyncRead for WinHTTPResponse {
                         Poll::Pending
                     }
                     WinHTTPCallbackEvent::DataWritten => {
-                        if self.ctx.buf_size == 0 {
-                            Poll::Ready(Ok(0))
+                        if self.ctx.buf_size() == 0 {
+                            Poll::Ready(self.finish_body().map(|_| false))
                         } else {
                             let r = unsafe {
                                 WinHttpQueryDataAvailable(**self.h_request, std::ptr::null_mut())
@@ -125,7 +368,10 @@ impl AsyncRead for WinHTTPResponse {
                     WinHTTPCallbackEvent::Error(err) => Poll::Ready(Err(err)),
                     _ => unreachable!(),
                 };
-                cx.waker().wake_by_ref();
+                // Events may have been sent while handling this one
+                if result.is_pending() {
+                    cx.waker().wake_by_ref();
+                }
                 result
             }
             Err(TryRecvError::Empty) => Poll::Pending,
@@ -134,4 +380,94 @@ impl AsyncRead for WinHTTPResponse {
             }
         }
     }
+
+    /// Hand out the unread data in the buffer without copying it when the
+    /// whole buffer is unread, a new buffer is allocated for the next read.
+    #[cfg(feature = "bytes")]
+    fn poll_chunk(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<Option<bytes::Bytes>>> {
+        match self.poll_fill(cx) {
+            Poll::Ready(Ok(true)) => {
+                let end = self.ctx.buf_size();
+                let chunk = if self.read_size == 0 {
+                    let len = self.buf.len();
+                    let mut data = Vec::from(std::mem::replace(
+                        &mut self.buf,
+                        vec![0; len].into_boxed_slice(),
+                    ));
+                    data.truncate(end);
+                    bytes::Bytes::from(data)
+                } else {
+                    bytes::Bytes::copy_from_slice(&self.buf[self.read_size..end])
+                };
+                self.total_read_size += end - self.read_size;
+                self.read_size = end;
+                Poll::Ready(Ok(Some(chunk)))
+            }
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(None)),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncRead for WinHTTPResponse {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<futures_lite::io::Result<usize>> {
+        match self.poll_fill(cx) {
+            Poll::Ready(Ok(true)) => {
+                let read_size = buf.len().min(self.ctx.buf_size() - self.read_size);
+                buf[..read_size]
+                    .copy_from_slice(&self.buf[self.read_size..self.read_size + read_size]);
+                self.read_size += read_size;
+                self.total_read_size += read_size;
+                Poll::Ready(Ok(read_size))
+            }
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(0)),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncBufRead for WinHTTPResponse {
+    fn poll_fill_buf(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<futures_lite::io::Result<&[u8]>> {
+        let this = self.get_mut();
+        match this.poll_fill(cx) {
+            Poll::Ready(Ok(true)) => {
+                Poll::Ready(Ok(&this.buf[this.read_size..this.ctx.buf_size()]))
+            }
+            Poll::Ready(Ok(false)) => Poll::Ready(Ok(&[])),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let amt = amt.min(this.ctx.buf_size().saturating_sub(this.read_size));
+        this.read_size += amt;
+        this.total_read_size += amt;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailer_names_are_lowercased() {
+        let trailers = parse_trailers("Grpc-Status: 0\r\ngrpc-message: OK \r\n\0");
+        assert_eq!(trailers.len(), 2);
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["grpc-message"], "OK");
+    }
 }